@@ -0,0 +1,233 @@
+//! Long-lived API keys for clients that can't sign in interactively, such
+//! as batch jobs.
+//!
+//! A key acts as the user who created it, with that user's role, but only
+//! for the [`Scope`]s it was created with. Keys are sent as
+//! `Authorization: Bearer ak_...` or in the `X-API-Key` header.
+
+use std::str::FromStr;
+
+use sea_orm::{
+    sea_query::Expr, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, QueryFilter, QuerySelect,
+};
+use serde::{Deserialize, Serialize};
+
+use super::token;
+use crate::entities::api_key;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Marks a bearer token as an API key rather than a JWT.
+const KEY_PREFIX: &str = "ak_";
+
+/// Characters of the key kept in clear, including [`KEY_PREFIX`].
+const DISPLAY_PREFIX_LENGTH: usize = 11;
+
+/// Don't write `last_used_at` more often than this, so busy keys don't
+/// turn every request into a write.
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+/// What an API key may do. Reads need `users:read`; anything that changes
+/// state needs `users:write`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scope {
+    #[serde(rename = "users:read")]
+    UsersRead,
+    #[serde(rename = "users:write")]
+    UsersWrite,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UsersRead => "users:read",
+            Self::UsersWrite => "users:write",
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "users:read" => Ok(Self::UsersRead),
+            "users:write" => Ok(Self::UsersWrite),
+            _ => Err(format!("unknown scope `{s}`")),
+        }
+    }
+}
+
+/// A freshly generated key. `key` is shown to the client once; only
+/// `hash` and `prefix` are stored.
+pub struct NewKey {
+    pub key: String,
+    pub prefix: String,
+    pub hash: Vec<u8>,
+}
+
+pub fn generate() -> NewKey {
+    let key = format!("{KEY_PREFIX}{}", token::generate());
+    NewKey {
+        prefix: key[..DISPLAY_PREFIX_LENGTH].to_string(),
+        hash: token::hash(&key),
+        key,
+    }
+}
+
+/// Whether a bearer token is an API key.
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(KEY_PREFIX)
+}
+
+/// Looks up a live key, returning its owner and scopes, and records the use.
+/// Whether the owner still exists is left to the caller.
+pub async fn authenticate<C: ConnectionTrait>(
+    db: &C,
+    key: &str,
+) -> Result<Option<(i32, Vec<Scope>)>, DbErr> {
+    let now = chrono::Utc::now().naive_utc();
+
+    let found = api_key::Entity::find()
+        .select_only()
+        .columns([
+            api_key::Column::Id,
+            api_key::Column::UserId,
+            api_key::Column::Scopes,
+        ])
+        .filter(api_key::Column::KeyHash.eq(token::hash(key)))
+        .filter(api_key::Column::RevokedAt.is_null())
+        .filter(
+            api_key::Column::ExpiresAt
+                .is_null()
+                .or(api_key::Column::ExpiresAt.gt(now)),
+        )
+        .into_tuple::<(i64, i32, Vec<String>)>()
+        .one(db)
+        .await?;
+
+    let Some((id, user_id, scopes)) = found else {
+        return Ok(None);
+    };
+
+    api_key::Entity::update_many()
+        .col_expr(api_key::Column::LastUsedAt, Expr::value(now))
+        .filter(api_key::Column::Id.eq(id))
+        .filter(
+            api_key::Column::LastUsedAt
+                .is_null()
+                .or(api_key::Column::LastUsedAt
+                    .lt(now - chrono::Duration::seconds(LAST_USED_RESOLUTION_SECS))),
+        )
+        .exec(db)
+        .await?;
+
+    // Unknown names can only come from a newer build; ignoring them errs
+    // on the side of granting less.
+    let scopes = scopes.iter().filter_map(|s| s.parse().ok()).collect();
+
+    Ok(Some((user_id, scopes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{ProxyRow, Value};
+
+    use super::*;
+    use crate::testing::{tuple, Script};
+
+    /// How a hash appears in the SQL the scripted database records.
+    fn literal(hash: &[u8]) -> String {
+        let hex: String = hash.iter().map(|b| format!("{b:02X}")).collect();
+        format!("'\\x{hex}'")
+    }
+
+    fn found(user_id: i32, scopes: &[&str]) -> Vec<ProxyRow> {
+        let scopes: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+        vec![tuple([
+            Value::from(1i64),
+            Value::from(user_id),
+            Value::from(scopes),
+        ])]
+    }
+
+    #[test]
+    fn generated_keys_store_only_a_prefix_and_hash() {
+        let new = generate();
+
+        assert!(is_api_key(&new.key));
+        assert!(new.key.starts_with(&new.prefix));
+        assert_eq!(new.prefix.len(), DISPLAY_PREFIX_LENGTH);
+        assert_eq!(new.hash, token::hash(&new.key));
+        assert_ne!(generate().key, new.key);
+    }
+
+    #[test]
+    fn jwts_are_not_api_keys() {
+        assert!(!is_api_key("eyJhbGciOiJIUzI1NiJ9.e30.sig"));
+    }
+
+    #[tokio::test]
+    async fn keys_are_looked_up_by_their_own_hash() {
+        let (key, other) = (generate(), generate());
+        let script = Script::new(vec![found(7, &["users:read"])]);
+        let db = script.connect().await;
+
+        let result = authenticate(&db, &key.key).await.unwrap();
+
+        assert_eq!(result, Some((7, vec![Scope::UsersRead])));
+        assert!(script.ran(&["FROM \"api_keys\"", &literal(&key.hash)]));
+        assert!(!script.ran(&[&literal(&other.hash)]));
+    }
+
+    #[tokio::test]
+    async fn revoked_and_expired_keys_are_rejected() {
+        let key = generate();
+        let script = Script::new(vec![vec![]]);
+        let db = script.connect().await;
+
+        let result = authenticate(&db, &key.key).await.unwrap();
+
+        assert_eq!(result, None);
+        assert!(script.ran(&[
+            "\"revoked_at\" IS NULL",
+            "\"expires_at\" IS NULL OR \"api_keys\".\"expires_at\" >",
+        ]));
+        assert!(!script.ran(&["UPDATE \"api_keys\""]));
+    }
+
+    #[tokio::test]
+    async fn uses_are_recorded() {
+        let key = generate();
+        let script = Script::new(vec![found(7, &["users:read"])]);
+        let db = script.connect().await;
+
+        authenticate(&db, &key.key).await.unwrap();
+
+        assert!(script.ran(&["UPDATE \"api_keys\"", "\"last_used_at\""]));
+    }
+
+    #[tokio::test]
+    async fn unknown_scopes_are_dropped() {
+        let key = generate();
+        let scopes = ["users:read", "users:write", "users:delete"];
+        let script = Script::new(vec![found(7, &scopes)]);
+        let db = script.connect().await;
+
+        let result = authenticate(&db, &key.key).await.unwrap();
+
+        assert_eq!(result, Some((7, vec![Scope::UsersRead, Scope::UsersWrite])));
+    }
+
+    #[test]
+    fn scopes_round_trip_through_their_names() {
+        for scope in [Scope::UsersRead, Scope::UsersWrite] {
+            assert_eq!(scope.as_str().parse(), Ok(scope));
+            assert_eq!(
+                serde_json::to_value(scope).unwrap(),
+                serde_json::json!(scope.as_str())
+            );
+        }
+        assert!("users:delete".parse::<Scope>().is_err());
+    }
+}
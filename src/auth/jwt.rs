@@ -0,0 +1,230 @@
+//! Short-lived access tokens signed with HS256 or EdDSA.
+//!
+//! The same keys sign single-purpose tokens, such as email verification
+//! links. Those carry an `aud` claim naming their purpose, which access
+//! token validation rejects, so one can never stand in for the other.
+
+use std::{sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::config::{JwtAlgorithm, JwtConfig};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    /// User ID.
+    pub sub: String,
+    pub iss: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// Claims of a single-purpose token; `data` holds the purpose's own claims.
+#[derive(Debug, Serialize, Deserialize)]
+struct PurposeClaims<T> {
+    sub: String,
+    iss: String,
+    aud: String,
+    iat: i64,
+    exp: i64,
+    #[serde(flatten)]
+    data: T,
+}
+
+/// Issues and verifies access tokens. Cheap to clone.
+#[derive(Clone)]
+pub struct Jwt {
+    keys: Arc<Keys>,
+    issuer: String,
+    pub access_ttl: Duration,
+    pub refresh_ttl: Duration,
+}
+
+struct Keys {
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+/// Extracts the DER body of a single-block PEM file.
+fn pem_to_der(pem: &str) -> Result<Vec<u8>, String> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect();
+    STANDARD
+        .decode(body)
+        .map_err(|e| format!("invalid PEM encoding: {e}"))
+}
+
+impl Jwt {
+    pub fn new(config: &JwtConfig) -> Result<Self, String> {
+        let keys = match config.algorithm {
+            JwtAlgorithm::Hs256 => {
+                let secret = config
+                    .secret
+                    .as_deref()
+                    .ok_or("jwt.secret: must be set for HS256 (APP_JWT_SECRET, JWT_SECRET or --jwt-secret)")?;
+                // RFC 7518 requires a key at least as long as the hash output
+                if secret.len() < 32 {
+                    return Err("jwt.secret: must be at least 32 bytes".to_string());
+                }
+                Keys {
+                    algorithm: Algorithm::HS256,
+                    encoding: EncodingKey::from_secret(secret.as_bytes()),
+                    decoding: DecodingKey::from_secret(secret.as_bytes()),
+                }
+            }
+            JwtAlgorithm::EdDsa => {
+                let path = config
+                    .private_key_file
+                    .as_deref()
+                    .ok_or("jwt.private_key_file: must be set for EdDSA")?;
+                let pem = std::fs::read_to_string(path)
+                    .map_err(|e| format!("jwt.private_key_file: cannot read {path}: {e}"))?;
+                let der = pem_to_der(&pem).map_err(|e| format!("jwt.private_key_file: {e}"))?;
+                // The verifying key is derived, so only the private key is configured
+                let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+                    .map_err(|e| format!("jwt.private_key_file: not a PKCS#8 Ed25519 key: {e}"))?;
+                Keys {
+                    algorithm: Algorithm::EdDSA,
+                    encoding: EncodingKey::from_ed_der(&der),
+                    decoding: DecodingKey::from_ed_der(pair.public_key().as_ref()),
+                }
+            }
+        };
+
+        Ok(Self {
+            keys: Arc::new(keys),
+            issuer: config.issuer.clone(),
+            access_ttl: config.access_ttl,
+            refresh_ttl: config.refresh_ttl,
+        })
+    }
+
+    /// Signs an access token for `user_id`.
+    pub fn issue(&self, user_id: i32) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = chrono::Utc::now().timestamp();
+        let claims = Claims {
+            sub: user_id.to_string(),
+            iss: self.issuer.clone(),
+            iat: now,
+            exp: now + self.access_ttl.as_secs() as i64,
+        };
+        jsonwebtoken::encode(
+            &Header::new(self.keys.algorithm),
+            &claims,
+            &self.keys.encoding,
+        )
+    }
+
+    /// Checks signature, algorithm, issuer and expiry, returning the user ID.
+    pub fn verify(&self, token: &str) -> Option<i32> {
+        let mut validation = Validation::new(self.keys.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+        validation.leeway = 0;
+
+        jsonwebtoken::decode::<Claims>(token, &self.keys.decoding, &validation)
+            .ok()
+            .and_then(|data| data.claims.sub.parse().ok())
+    }
+
+    /// Signs a token for `user_id` that only [`Jwt::verify_for`] with the
+    /// same `audience` accepts.
+    pub fn issue_for<T: Serialize>(
+        &self,
+        audience: &str,
+        user_id: i32,
+        ttl: Duration,
+        data: T,
+    ) -> Result<String, jsonwebtoken::errors::Error> {
+        let now = chrono::Utc::now().timestamp();
+        let claims = PurposeClaims {
+            sub: user_id.to_string(),
+            iss: self.issuer.clone(),
+            aud: audience.to_string(),
+            iat: now,
+            exp: now + ttl.as_secs() as i64,
+            data,
+        };
+        jsonwebtoken::encode(
+            &Header::new(self.keys.algorithm),
+            &claims,
+            &self.keys.encoding,
+        )
+    }
+
+    /// Checks a token from [`Jwt::issue_for`], returning the user ID and
+    /// the purpose's claims.
+    pub fn verify_for<T: DeserializeOwned>(&self, audience: &str, token: &str) -> Option<(i32, T)> {
+        let mut validation = Validation::new(self.keys.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "sub", "aud"]);
+        validation.leeway = 0;
+
+        let claims =
+            jsonwebtoken::decode::<PurposeClaims<T>>(token, &self.keys.decoding, &validation)
+                .ok()?
+                .claims;
+        Some((claims.sub.parse().ok()?, claims.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Data {
+        email: String,
+    }
+
+    fn jwt() -> Jwt {
+        let mut config = Config::default().jwt;
+        config.secret = Some("0123456789abcdef0123456789abcdef".to_string());
+        Jwt::new(&config).unwrap()
+    }
+
+    fn data() -> Data {
+        Data {
+            email: "jane@example.com".to_string(),
+        }
+    }
+
+    #[test]
+    fn purpose_tokens_round_trip() {
+        let jwt = jwt();
+        let token = jwt
+            .issue_for("verify-email", 7, Duration::from_secs(60), data())
+            .unwrap();
+        assert_eq!(jwt.verify_for("verify-email", &token), Some((7, data())));
+    }
+
+    #[test]
+    fn purpose_tokens_need_their_audience() {
+        let jwt = jwt();
+        let token = jwt
+            .issue_for("verify-email", 7, Duration::from_secs(60), data())
+            .unwrap();
+        assert_eq!(jwt.verify_for::<Data>("reset-password", &token), None);
+    }
+
+    #[test]
+    fn purpose_tokens_are_not_access_tokens() {
+        let jwt = jwt();
+        let token = jwt
+            .issue_for("verify-email", 7, Duration::from_secs(60), data())
+            .unwrap();
+        assert_eq!(jwt.verify(&token), None);
+        let access = jwt.issue(7).unwrap();
+        assert_eq!(jwt.verify(&access), Some(7));
+        assert_eq!(jwt.verify_for::<Data>("verify-email", &access), None);
+    }
+}
@@ -0,0 +1,208 @@
+//! Caller authentication.
+//!
+//! Requests authenticate with a JWT access token (`Authorization: Bearer`),
+//! obtained from `POST /auth/login` and renewed with a refresh token, or
+//! with an API key (see [`api_key`]) resolving to the same [`Principal`].
+//! Browsers use a session cookie instead (see [`session`]).
+//! Operators may instead present the shared `ADMIN_TOKEN` in the
+//! `X-Admin-Token` header, which carries the admin role. If no admin token
+//! is configured, only users with the admin role are admins.
+//!
+//! Users whose role requires two-factor authentication (see [`totp`]) are
+//! refused until they enable it, except where [`AllowUnenrolled`] is used.
+
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts},
+};
+
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QuerySelect};
+
+use crate::{
+    entities::user::{self, Role},
+    error::AppError,
+    AppState,
+};
+
+pub mod api_key;
+pub mod jwt;
+pub mod password_reset;
+pub mod policy;
+pub mod refresh;
+pub mod session;
+pub mod token;
+pub mod totp;
+pub mod verify_email;
+
+use api_key::{Scope, API_KEY_HEADER};
+
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+/// The authenticated caller. Rejects unauthenticated requests with `401`;
+/// see [`policy`] for what each caller may do.
+pub struct Principal {
+    /// The signed-in user, or `None` for the admin token.
+    pub user_id: Option<i32>,
+    pub role: Role,
+    /// What an API key is limited to; `None` when the caller didn't use one.
+    pub scopes: Option<Vec<Scope>>,
+    /// The session the caller authenticated with, if any.
+    pub session_id: Option<i64>,
+}
+
+impl FromRequestParts<AppState> for Principal {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        let AllowUnenrolled(principal) = AllowUnenrolled::from_request_parts(parts, state).await?;
+        if let Some(user_id) = principal.user_id {
+            totp::require_enrolled(&state.db, user_id, principal.role).await?;
+        }
+        Ok(principal)
+    }
+}
+
+/// A [`Principal`] that may not have enabled the two-factor authentication
+/// their role requires yet, for the endpoints that let them do so.
+pub struct AllowUnenrolled(pub Principal);
+
+impl FromRequestParts<AppState> for AllowUnenrolled {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        let presented = parts
+            .headers
+            .get(ADMIN_TOKEN_HEADER)
+            .and_then(|v| v.to_str().ok());
+
+        if let (Some(expected), Some(presented)) = (state.admin_token.as_deref(), presented) {
+            if constant_time_eq(expected, presented) {
+                return Ok(Self(Principal {
+                    user_id: None,
+                    role: Role::Admin,
+                    scopes: None,
+                    session_id: None,
+                }));
+            }
+        }
+
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim);
+        let api_key = parts
+            .headers
+            .get(API_KEY_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .or(bearer.filter(|token| api_key::is_api_key(token)));
+
+        let (user_id, scopes, session_id) = match (api_key, bearer) {
+            (Some(key), _) => api_key::authenticate(&state.db, key)
+                .await?
+                .map(|(user_id, scopes)| (user_id, Some(scopes), None))
+                .ok_or_else(|| {
+                    AppError::Unauthorized("invalid, expired or revoked API key".to_string())
+                })?,
+            (None, Some(token)) => state
+                .jwt
+                .verify(token)
+                .map(|user_id| (user_id, None, None))
+                .ok_or_else(|| {
+                    AppError::Unauthorized("invalid or expired access token".to_string())
+                })?,
+            (None, None) => {
+                let token = session::cookie(&parts.headers, &state.session.cookie_name)
+                    .ok_or_else(|| {
+                        AppError::Unauthorized(
+                            "missing access token, API key or session".to_string(),
+                        )
+                    })?;
+                let csrf_token = parts
+                    .headers
+                    .get(session::CSRF_HEADER)
+                    .and_then(|v| v.to_str().ok());
+                // Browsers attach cookies to cross-site requests too, so
+                // state changes must prove the page could read the CSRF cookie
+                let (session_id, user_id) = session::authenticate(
+                    &state.db,
+                    &state.session,
+                    token,
+                    csrf_token,
+                    !parts.method.is_safe(),
+                )
+                .await?;
+                (user_id, None, Some(session_id))
+            }
+        };
+
+        // Read the role on every request, so role changes and deletions
+        // apply immediately rather than when the access token expires.
+        let role = user::Entity::find_by_id(user_id)
+            .select_only()
+            .column(user::Column::Role)
+            .filter(user::Column::DeletedAt.is_null())
+            .into_tuple::<Role>()
+            .one(&state.db)
+            .await?
+            .ok_or_else(|| AppError::Unauthorized("account no longer exists".to_string()))?;
+
+        tracing::Span::current().record("user_id", user_id);
+        let principal = Principal {
+            user_id: Some(user_id),
+            role,
+            scopes,
+            session_id,
+        };
+
+        principal.require_scope(if parts.method.is_safe() {
+            Scope::UsersRead
+        } else {
+            Scope::UsersWrite
+        })?;
+
+        Ok(Self(principal))
+    }
+}
+
+impl Principal {
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
+
+    pub fn require_admin(&self) -> Result<(), AppError> {
+        if self.is_admin() {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(
+                "this operation requires admin privileges".to_string(),
+            ))
+        }
+    }
+}
+
+/// Rejects the request with `401` if unauthenticated, or `403` unless the
+/// caller is an admin.
+pub struct RequireAdmin;
+
+impl FromRequestParts<AppState> for RequireAdmin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        Principal::from_request_parts(parts, state)
+            .await?
+            .require_admin()?;
+        Ok(Self)
+    }
+}
+
+/// Compares secrets without short-circuiting on the first differing byte.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
@@ -0,0 +1,255 @@
+//! Single-use password reset links.
+//!
+//! Requesting a reset emails a random token whose hash is stored with an
+//! expiry. Redeeming it once sets a new password; the token is then spent,
+//! as are older tokens when a newer one is sent or the email changes. A new
+//! link is sent at most every few minutes, so nobody can flood an inbox or
+//! keep cancelling the link the user is about to open.
+
+use std::time::Duration;
+
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait,
+    PaginatorTrait, QueryFilter, QuerySelect, Set,
+};
+
+use super::token;
+use crate::{
+    entities::{password_reset_token, user},
+    error::AppError,
+    mail::{self, Message},
+    AppState,
+};
+
+/// Minimum time between two links to the same user.
+pub const RESEND_INTERVAL: Duration = Duration::from_secs(300);
+
+fn rejected() -> AppError {
+    AppError::BadRequest("reset token is invalid, expired or already used".to_string())
+}
+
+/// Spends every outstanding token of `user_id`.
+pub async fn invalidate<C: ConnectionTrait>(db: &C, user_id: i32) -> Result<(), DbErr> {
+    password_reset_token::Entity::update_many()
+        .col_expr(
+            password_reset_token::Column::UsedAt,
+            Expr::value(chrono::Utc::now().naive_utc()),
+        )
+        .filter(password_reset_token::Column::UserId.eq(user_id))
+        .filter(password_reset_token::Column::UsedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Emails `user` a reset link, replacing any link sent before, unless one
+/// was sent in the last few minutes. Mail failures are logged; the user can
+/// request another link.
+pub async fn send(state: &AppState, user: &user::Model) -> Result<(), AppError> {
+    let token = token::generate();
+    let now = chrono::Utc::now().naive_utc();
+    let ttl = chrono::Duration::from_std(state.password_reset_ttl)
+        .map_err(|e| AppError::Internal(format!("reset token lifetime out of range: {e}")))?;
+
+    let resend_interval = chrono::Duration::from_std(RESEND_INTERVAL)
+        .map_err(|e| AppError::Internal(format!("resend interval out of range: {e}")))?;
+
+    let txn = state.db.begin().await?;
+
+    // Locking the user makes concurrent requests take turns, so only the
+    // first of a burst sends a link
+    user::Entity::find_by_id(user.id)
+        .lock_exclusive()
+        .one(&txn)
+        .await?;
+    let recently_sent = password_reset_token::Entity::find()
+        .filter(password_reset_token::Column::UserId.eq(user.id))
+        .filter(password_reset_token::Column::CreatedAt.gt(now - resend_interval))
+        .count(&txn)
+        .await?
+        > 0;
+    if recently_sent {
+        tracing::info!(
+            user_id = user.id,
+            "password reset link sent recently, not sending another"
+        );
+        return Ok(());
+    }
+
+    invalidate(&txn, user.id).await?;
+    password_reset_token::ActiveModel {
+        user_id: Set(user.id),
+        token_hash: Set(token::hash(&token)),
+        created_at: Set(now),
+        expires_at: Set(now + ttl),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await?;
+    txn.commit().await?;
+
+    let message = Message {
+        to: user.email.clone(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Hi {},\n\nOpen this link to choose a new password:\n\n{}\n\n\
+             The link expires in {} minutes. If you didn't ask to reset your \
+             password, you can ignore this email.\n",
+            user.name,
+            mail::link(&state.reset_password_url, &token),
+            state.password_reset_ttl.as_secs().div_ceil(60),
+        ),
+    };
+
+    match state.mailer.send(message).await {
+        Ok(()) => tracing::info!(user_id = user.id, "sent password reset email"),
+        Err(e) => tracing::error!(
+            user_id = user.id,
+            "failed to send password reset email: {e}"
+        ),
+    }
+    Ok(())
+}
+
+/// Spends `token`, returning the user it was sent to, locked for update.
+/// Must run in the transaction that sets the password, so a failure leaves
+/// the token unspent.
+pub async fn redeem<C: ConnectionTrait>(db: &C, token: &str) -> Result<user::Model, AppError> {
+    let now = chrono::Utc::now().naive_utc();
+
+    let stored = password_reset_token::Entity::find()
+        .filter(password_reset_token::Column::TokenHash.eq(token::hash(token)))
+        .lock_exclusive()
+        .one(db)
+        .await?
+        .filter(|t| t.used_at.is_none() && t.expires_at > now)
+        .ok_or_else(rejected)?;
+
+    let user_id = stored.user_id;
+    let mut stored: password_reset_token::ActiveModel = stored.into();
+    stored.used_at = Set(Some(now));
+    stored.update(db).await?;
+
+    user::Entity::find_by_id(user_id)
+        .filter(user::Column::DeletedAt.is_null())
+        .lock_exclusive()
+        .one(db)
+        .await?
+        .ok_or_else(rejected)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDateTime, Utc};
+
+    use super::*;
+    use crate::{
+        entities::user::Role,
+        testing::{model_row, Script},
+    };
+
+    fn stored(token: &str, expires_at: NaiveDateTime) -> password_reset_token::Model {
+        password_reset_token::Model {
+            id: 1,
+            user_id: 7,
+            token_hash: token::hash(token),
+            created_at: Utc::now().naive_utc(),
+            expires_at,
+            used_at: None,
+        }
+    }
+
+    fn live(token: &str) -> password_reset_token::Model {
+        stored(token, Utc::now().naive_utc() + chrono::Duration::hours(1))
+    }
+
+    fn owner() -> user::Model {
+        let now = Utc::now().naive_utc();
+        user::Model {
+            id: 7,
+            name: "Jane".to_string(),
+            email: "jane@example.com".to_string(),
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            deleted_at: None,
+            password_hash: None,
+            role: Role::Member,
+            email_verified_at: None,
+            email_verification_nonce: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn redeeming_spends_the_token() {
+        let token = live("token");
+        let script = Script::new(vec![
+            vec![model_row(&token)],
+            vec![model_row(&token)],
+            vec![model_row(&owner())],
+        ]);
+        let db = script.connect().await;
+
+        let user = redeem(&db, "token").await.unwrap();
+
+        assert_eq!(user.id, 7);
+        assert!(script.ran(&["UPDATE \"password_reset_tokens\"", "\"used_at\""]));
+    }
+
+    #[tokio::test]
+    async fn tokens_work_only_once() {
+        let mut used = live("used");
+        used.used_at = Some(Utc::now().naive_utc());
+        let script = Script::new(vec![vec![model_row(&used)]]);
+        let db = script.connect().await;
+
+        let result = redeem(&db, "used").await;
+
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+        assert!(!script.ran(&["UPDATE \"password_reset_tokens\""]));
+    }
+
+    #[tokio::test]
+    async fn expired_tokens_are_rejected() {
+        let expired = stored(
+            "expired",
+            Utc::now().naive_utc() - chrono::Duration::seconds(1),
+        );
+        let script = Script::new(vec![vec![model_row(&expired)]]);
+        let db = script.connect().await;
+
+        let result = redeem(&db, "expired").await;
+
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+        assert!(!script.ran(&["UPDATE \"password_reset_tokens\""]));
+    }
+
+    #[tokio::test]
+    async fn tokens_of_deleted_users_are_rejected() {
+        let token = live("token");
+        let script = Script::new(vec![
+            vec![model_row(&token)],
+            vec![model_row(&token)],
+            vec![],
+        ]);
+        let db = script.connect().await;
+
+        let result = redeem(&db, "token").await;
+
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn invalidating_spends_every_outstanding_token() {
+        let script = Script::new(vec![]);
+        let db = script.connect().await;
+
+        invalidate(&db, 7).await.unwrap();
+
+        assert!(script.ran(&[
+            "UPDATE \"password_reset_tokens\"",
+            "\"user_id\" = 7",
+            "\"used_at\" IS NULL"
+        ]));
+    }
+}
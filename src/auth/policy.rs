@@ -0,0 +1,230 @@
+//! Who may do what to which user.
+//!
+//! Admins manage every user. Members may only read and update their own
+//! record, and nobody may change their own role, so an admin can't demote
+//! themselves and leave nobody to undo it. API keys are further limited to
+//! their scopes and can't manage API keys.
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+
+use super::{api_key::Scope, Principal};
+use crate::{error::AppError, extract::AppPath, AppState};
+
+impl Principal {
+    /// Whether the caller may read and modify user `id`.
+    pub fn can_access_user(&self, id: i32) -> bool {
+        self.is_admin() || self.user_id == Some(id)
+    }
+
+    /// Whether the caller may change the role of user `id`.
+    pub fn can_change_role(&self, id: i32) -> bool {
+        self.is_admin() && self.user_id != Some(id)
+    }
+
+    /// Whether the caller may do what `scope` covers. Only API keys are
+    /// limited by scope.
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes
+            .as_ref()
+            .is_none_or(|scopes| scopes.contains(&scope))
+    }
+
+    pub fn require_scope(&self, scope: Scope) -> Result<(), AppError> {
+        if self.has_scope(scope) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(format!(
+                "API key lacks the `{}` scope",
+                scope.as_str()
+            )))
+        }
+    }
+
+    /// The ID of a user who signed in, as opposed to presenting the admin
+    /// token or an API key.
+    pub fn require_signed_in_user(&self) -> Result<i32, AppError> {
+        match (self.user_id, &self.scopes) {
+            (Some(id), None) => Ok(id),
+            (None, _) => Err(AppError::Forbidden(
+                "the admin token is not tied to a user; sign in instead".to_string(),
+            )),
+            (Some(_), Some(_)) => Err(AppError::Forbidden(
+                "this operation is not available to API keys".to_string(),
+            )),
+        }
+    }
+
+    /// Only the signed-in user `id` themselves, e.g. to set up their
+    /// second factor.
+    pub fn require_own_account(&self, id: i32) -> Result<(), AppError> {
+        if self.require_signed_in_user()? == id {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(
+                "this operation is only available for your own account".to_string(),
+            ))
+        }
+    }
+
+    pub fn require_user_access(&self, id: i32) -> Result<(), AppError> {
+        if self.can_access_user(id) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(
+                "you can only access your own account".to_string(),
+            ))
+        }
+    }
+
+    pub fn require_role_change(&self, id: i32) -> Result<(), AppError> {
+        self.require_admin()?;
+        if self.can_change_role(id) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(
+                "you cannot change your own role".to_string(),
+            ))
+        }
+    }
+}
+
+/// The `{id}` of a user the caller may read and modify, taken from the
+/// path. Rejects with `403` when a member names someone else.
+pub struct UserAccess {
+    pub principal: Principal,
+    pub id: i32,
+}
+
+impl FromRequestParts<AppState> for UserAccess {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        let principal = Principal::from_request_parts(parts, state).await?;
+        let AppPath(id) = AppPath::<i32>::from_request_parts(parts, state).await?;
+        principal.require_user_access(id)?;
+        Ok(Self { principal, id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entities::user::Role;
+
+    fn member(id: i32) -> Principal {
+        Principal {
+            user_id: Some(id),
+            role: Role::Member,
+            scopes: None,
+            session_id: None,
+        }
+    }
+
+    fn admin(id: i32) -> Principal {
+        Principal {
+            user_id: Some(id),
+            role: Role::Admin,
+            scopes: None,
+            session_id: None,
+        }
+    }
+
+    fn admin_token() -> Principal {
+        Principal {
+            user_id: None,
+            role: Role::Admin,
+            scopes: None,
+            session_id: None,
+        }
+    }
+
+    fn api_key(id: i32, scopes: &[Scope]) -> Principal {
+        Principal {
+            user_id: Some(id),
+            role: Role::Member,
+            scopes: Some(scopes.to_vec()),
+            session_id: None,
+        }
+    }
+
+    #[test]
+    fn members_access_only_themselves() {
+        assert!(member(1).can_access_user(1));
+        assert!(!member(1).can_access_user(2));
+        assert!(member(1).require_user_access(2).is_err());
+    }
+
+    #[test]
+    fn admins_access_everyone() {
+        assert!(admin(1).can_access_user(1));
+        assert!(admin(1).can_access_user(2));
+        assert!(admin_token().can_access_user(2));
+    }
+
+    #[test]
+    fn members_cannot_change_roles() {
+        assert!(!member(1).can_change_role(1));
+        assert!(!member(1).can_change_role(2));
+        assert!(matches!(
+            member(1).require_role_change(2),
+            Err(AppError::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn admins_change_roles_of_others_only() {
+        assert!(admin(1).can_change_role(2));
+        assert!(!admin(1).can_change_role(1));
+        assert!(admin(1).require_role_change(1).is_err());
+    }
+
+    #[test]
+    fn admin_token_changes_any_role() {
+        assert!(admin_token().can_change_role(1));
+        assert!(admin_token().require_role_change(1).is_ok());
+    }
+
+    #[test]
+    fn only_admins_pass_require_admin() {
+        assert!(admin(1).require_admin().is_ok());
+        assert!(admin_token().require_admin().is_ok());
+        assert!(member(1).require_admin().is_err());
+    }
+
+    #[test]
+    fn api_keys_are_limited_to_their_scopes() {
+        let read_only = api_key(1, &[Scope::UsersRead]);
+        assert!(read_only.has_scope(Scope::UsersRead));
+        assert!(!read_only.has_scope(Scope::UsersWrite));
+        assert!(read_only.require_scope(Scope::UsersWrite).is_err());
+        assert!(member(1).has_scope(Scope::UsersWrite));
+        assert!(admin_token().has_scope(Scope::UsersWrite));
+    }
+
+    #[test]
+    fn api_keys_keep_their_owners_access() {
+        let key = api_key(1, &[Scope::UsersRead, Scope::UsersWrite]);
+        assert!(key.can_access_user(1));
+        assert!(!key.can_access_user(2));
+    }
+
+    #[test]
+    fn only_the_account_owner_sets_up_two_factor() {
+        assert!(member(1).require_own_account(1).is_ok());
+        assert!(member(1).require_own_account(2).is_err());
+        assert!(admin(1).require_own_account(2).is_err());
+        assert!(admin_token().require_own_account(1).is_err());
+        assert!(api_key(1, &[Scope::UsersWrite])
+            .require_own_account(1)
+            .is_err());
+    }
+
+    #[test]
+    fn only_signed_in_users_manage_api_keys() {
+        assert_eq!(member(1).require_signed_in_user().ok(), Some(1));
+        assert!(admin_token().require_signed_in_user().is_err());
+        assert!(api_key(1, &[Scope::UsersWrite])
+            .require_signed_in_user()
+            .is_err());
+    }
+}
@@ -0,0 +1,256 @@
+//! Opaque refresh tokens stored in the database.
+//!
+//! Each refresh exchanges the presented token for a new one in the same
+//! family. A token is good for one exchange: presenting it again means it
+//! was copied, so the whole family is revoked and the holder of the latest
+//! token has to sign in again too.
+
+use std::time::Duration;
+
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, PaginatorTrait, QueryFilter, QuerySelect, Set,
+};
+use uuid::Uuid;
+
+use super::token::{self, hash};
+use crate::{
+    db::Traced,
+    entities::{refresh_token, user},
+    error::AppError,
+};
+
+fn rejected() -> AppError {
+    AppError::Unauthorized("invalid or expired refresh token".to_string())
+}
+
+/// Stores a new token for `user_id` and returns it. Pass `None` as the
+/// family to start a new one, i.e. on sign-in.
+pub async fn issue<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+    family_id: Option<Uuid>,
+    ttl: Duration,
+) -> Result<String, AppError> {
+    let token = token::generate();
+
+    let now = chrono::Utc::now().naive_utc();
+    let ttl = chrono::Duration::from_std(ttl)
+        .map_err(|e| AppError::Internal(format!("refresh token lifetime out of range: {e}")))?;
+
+    refresh_token::ActiveModel {
+        user_id: Set(user_id),
+        family_id: Set(family_id.unwrap_or_else(Uuid::new_v4)),
+        token_hash: Set(hash(&token)),
+        created_at: Set(now),
+        expires_at: Set(now + ttl),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok(token)
+}
+
+/// Exchanges `token` for a new one, returning the owner's ID and the new token.
+pub async fn rotate(
+    db: &Traced<DatabaseConnection>,
+    token: &str,
+    ttl: Duration,
+) -> Result<(i32, String), AppError> {
+    let txn = db.begin().await?;
+
+    let stored = refresh_token::Entity::find()
+        .filter(refresh_token::Column::TokenHash.eq(hash(token)))
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+        .ok_or_else(rejected)?;
+
+    let now = chrono::Utc::now().naive_utc();
+
+    if stored.used_at.is_some() || stored.revoked_at.is_some() {
+        if stored.revoked_at.is_none() {
+            revoke_family(&txn, stored.family_id).await?;
+            txn.commit().await?;
+            tracing::warn!(
+                user_id = stored.user_id,
+                family_id = %stored.family_id,
+                "refresh token reused, revoked its family"
+            );
+        }
+        return Err(rejected());
+    }
+    if stored.expires_at <= now {
+        return Err(rejected());
+    }
+
+    let live = user::Entity::find_by_id(stored.user_id)
+        .filter(user::Column::DeletedAt.is_null())
+        .count(&txn)
+        .await?;
+    if live == 0 {
+        return Err(rejected());
+    }
+
+    let user_id = stored.user_id;
+    let family_id = stored.family_id;
+    let mut stored: refresh_token::ActiveModel = stored.into();
+    stored.used_at = Set(Some(now));
+    stored.update(&txn).await?;
+
+    let token = issue(&txn, user_id, Some(family_id), ttl).await?;
+
+    txn.commit().await?;
+
+    Ok((user_id, token))
+}
+
+/// Revokes the family `token` belongs to. Unknown tokens are ignored.
+pub async fn revoke<C: ConnectionTrait>(db: &C, token: &str) -> Result<(), AppError> {
+    let family_id = refresh_token::Entity::find()
+        .select_only()
+        .column(refresh_token::Column::FamilyId)
+        .filter(refresh_token::Column::TokenHash.eq(hash(token)))
+        .into_tuple::<Uuid>()
+        .one(db)
+        .await?;
+
+    if let Some(family_id) = family_id {
+        revoke_family(db, family_id).await?;
+    }
+    Ok(())
+}
+
+/// Revokes every refresh token of `user_id`, signing them out everywhere.
+pub async fn revoke_all<C: ConnectionTrait>(db: &C, user_id: i32) -> Result<(), DbErr> {
+    refresh_token::Entity::update_many()
+        .col_expr(
+            refresh_token::Column::RevokedAt,
+            Expr::value(chrono::Utc::now().naive_utc()),
+        )
+        .filter(refresh_token::Column::UserId.eq(user_id))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+async fn revoke_family<C: ConnectionTrait>(db: &C, family_id: Uuid) -> Result<(), AppError> {
+    refresh_token::Entity::update_many()
+        .col_expr(
+            refresh_token::Column::RevokedAt,
+            Expr::value(chrono::Utc::now().naive_utc()),
+        )
+        .filter(refresh_token::Column::FamilyId.eq(family_id))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDateTime, Utc};
+
+    use super::*;
+    use crate::testing::{count, model_row, Script};
+
+    const TTL: Duration = Duration::from_secs(3600);
+
+    fn stored(token: &str, expires_at: NaiveDateTime) -> refresh_token::Model {
+        refresh_token::Model {
+            id: 1,
+            user_id: 7,
+            family_id: Uuid::new_v4(),
+            token_hash: hash(token),
+            created_at: Utc::now().naive_utc(),
+            expires_at,
+            used_at: None,
+            revoked_at: None,
+        }
+    }
+
+    fn live(token: &str) -> refresh_token::Model {
+        stored(token, Utc::now().naive_utc() + chrono::Duration::hours(1))
+    }
+
+    #[tokio::test]
+    async fn tokens_rotate_into_the_same_family() {
+        let current = live("current");
+        let script = Script::new(vec![
+            vec![model_row(&current)],
+            vec![count(1)],
+            vec![model_row(&current)],
+            vec![model_row(&live("next"))],
+        ]);
+        let db = Traced(script.connect().await);
+
+        let (user_id, token) = rotate(&db, "current", TTL).await.unwrap();
+
+        assert_eq!(user_id, 7);
+        assert_ne!(token, "current");
+        assert!(script.ran(&["UPDATE \"refresh_tokens\"", "\"used_at\""]));
+        assert!(script.ran(&[
+            "INSERT INTO \"refresh_tokens\"",
+            &current.family_id.to_string()
+        ]));
+    }
+
+    #[tokio::test]
+    async fn reusing_a_rotated_token_revokes_its_family() {
+        let mut used = live("used");
+        used.used_at = Some(Utc::now().naive_utc());
+        let script = Script::new(vec![vec![model_row(&used)]]);
+        let db = Traced(script.connect().await);
+
+        let result = rotate(&db, "used", TTL).await;
+
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+        assert!(script.ran(&[
+            "UPDATE \"refresh_tokens\"",
+            "\"revoked_at\"",
+            &used.family_id.to_string()
+        ]));
+    }
+
+    #[tokio::test]
+    async fn revoked_tokens_are_rejected_without_further_writes() {
+        let mut revoked = live("revoked");
+        revoked.used_at = Some(Utc::now().naive_utc());
+        revoked.revoked_at = Some(Utc::now().naive_utc());
+        let script = Script::new(vec![vec![model_row(&revoked)]]);
+        let db = Traced(script.connect().await);
+
+        let result = rotate(&db, "revoked", TTL).await;
+
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+        assert!(!script.ran(&["UPDATE \"refresh_tokens\""]));
+    }
+
+    #[tokio::test]
+    async fn expired_tokens_are_rejected() {
+        let expired = stored(
+            "expired",
+            Utc::now().naive_utc() - chrono::Duration::seconds(1),
+        );
+        let script = Script::new(vec![vec![model_row(&expired)]]);
+        let db = Traced(script.connect().await);
+
+        let result = rotate(&db, "expired", TTL).await;
+
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+        assert!(!script.ran(&["UPDATE \"refresh_tokens\""]));
+        assert!(!script.ran(&["INSERT INTO \"refresh_tokens\""]));
+    }
+
+    #[tokio::test]
+    async fn unknown_tokens_are_rejected() {
+        let script = Script::new(vec![vec![]]);
+        let db = Traced(script.connect().await);
+
+        let result = rotate(&db, "unknown", TTL).await;
+
+        assert!(matches!(result, Err(AppError::Unauthorized(_))));
+    }
+}
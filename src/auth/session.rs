@@ -0,0 +1,389 @@
+//! Server-side sessions for browser clients.
+//!
+//! Signing in sets two cookies: the `HttpOnly` session cookie, and a CSRF
+//! cookie that the frontend reads and echoes in the `X-CSRF-Token` header
+//! of every state-changing request. Another site can make the browser send
+//! the cookies but can't read them, so it can't forge the header.
+//!
+//! A session ends after [`SessionConfig::idle_timeout`] without requests,
+//! [`SessionConfig::absolute_timeout`] after sign-in, or when revoked.
+
+use axum::http::{header, HeaderMap, HeaderValue};
+use chrono::NaiveDateTime;
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait,
+    QueryFilter, QueryOrder, Set,
+};
+
+use super::token;
+use crate::{
+    config::{SameSite, SessionConfig},
+    entities::session,
+    error::AppError,
+    validation::MAX_TEXT_LENGTH,
+};
+
+pub const CSRF_HEADER: &str = "x-csrf-token";
+pub const CSRF_COOKIE: &str = "csrf_token";
+
+/// Don't write `last_seen_at` more often than this.
+const TOUCH_RESOLUTION_SECS: i64 = 60;
+
+/// A session just started. The tokens are only ever sent to the client.
+pub struct NewSession {
+    pub id: i64,
+    pub token: String,
+    pub csrf_token: String,
+    pub expires_at: NaiveDateTime,
+}
+
+fn duration(std: std::time::Duration) -> Result<chrono::Duration, AppError> {
+    chrono::Duration::from_std(std)
+        .map_err(|e| AppError::Internal(format!("session timeout out of range: {e}")))
+}
+
+/// Earliest `last_seen_at` of a session that hasn't gone idle.
+fn idle_cutoff(config: &SessionConfig, now: NaiveDateTime) -> Result<NaiveDateTime, AppError> {
+    Ok(now - duration(config.idle_timeout)?)
+}
+
+pub async fn create<C: ConnectionTrait>(
+    db: &C,
+    config: &SessionConfig,
+    user_id: i32,
+    user_agent: Option<&str>,
+) -> Result<NewSession, AppError> {
+    let token = token::generate();
+    let csrf_token = token::generate();
+    let now = chrono::Utc::now().naive_utc();
+    let expires_at = now + duration(config.absolute_timeout)?;
+    let user_agent = user_agent.map(|ua| ua.chars().take(MAX_TEXT_LENGTH as usize).collect());
+
+    let session = session::ActiveModel {
+        user_id: Set(user_id),
+        token_hash: Set(token::hash(&token)),
+        csrf_hash: Set(token::hash(&csrf_token)),
+        user_agent: Set(user_agent),
+        created_at: Set(now),
+        last_seen_at: Set(now),
+        expires_at: Set(expires_at),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok(NewSession {
+        id: session.id,
+        token,
+        csrf_token,
+        expires_at: session.expires_at,
+    })
+}
+
+/// Resolves a session cookie to `(session_id, user_id)` and records the
+/// activity. With `check_csrf`, `csrf_token` must match the session's.
+pub async fn authenticate<C: ConnectionTrait>(
+    db: &C,
+    config: &SessionConfig,
+    token: &str,
+    csrf_token: Option<&str>,
+    check_csrf: bool,
+) -> Result<(i64, i32), AppError> {
+    let now = chrono::Utc::now().naive_utc();
+
+    let session = session::Entity::find()
+        .filter(session::Column::TokenHash.eq(token::hash(token)))
+        .filter(session::Column::RevokedAt.is_null())
+        .filter(session::Column::ExpiresAt.gt(now))
+        .filter(session::Column::LastSeenAt.gt(idle_cutoff(config, now)?))
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("session expired or revoked".to_string()))?;
+
+    // Both sides are hashes of the same length, so comparing them leaks
+    // nothing useful about the token.
+    if check_csrf && csrf_token.map(token::hash).as_ref() != Some(&session.csrf_hash) {
+        return Err(AppError::Forbidden(
+            "missing or invalid CSRF token".to_string(),
+        ));
+    }
+
+    if session.last_seen_at < now - chrono::Duration::seconds(TOUCH_RESOLUTION_SECS) {
+        session::Entity::update_many()
+            .col_expr(session::Column::LastSeenAt, Expr::value(now))
+            .filter(session::Column::Id.eq(session.id))
+            .exec(db)
+            .await?;
+    }
+
+    Ok((session.id, session.user_id))
+}
+
+/// Sessions of `user_id` that haven't ended, oldest first.
+pub async fn list_active<C: ConnectionTrait>(
+    db: &C,
+    config: &SessionConfig,
+    user_id: i32,
+) -> Result<Vec<session::Model>, AppError> {
+    let now = chrono::Utc::now().naive_utc();
+    Ok(session::Entity::find()
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::RevokedAt.is_null())
+        .filter(session::Column::ExpiresAt.gt(now))
+        .filter(session::Column::LastSeenAt.gt(idle_cutoff(config, now)?))
+        .order_by_asc(session::Column::Id)
+        .all(db)
+        .await?)
+}
+
+/// Ends session `id` of `user_id`. Returns `false` if there was no such
+/// session still open.
+pub async fn revoke<C: ConnectionTrait>(db: &C, user_id: i32, id: i64) -> Result<bool, DbErr> {
+    let result = session::Entity::update_many()
+        .col_expr(
+            session::Column::RevokedAt,
+            Expr::value(chrono::Utc::now().naive_utc()),
+        )
+        .filter(session::Column::Id.eq(id))
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+/// Ends every open session of `user_id`.
+pub async fn revoke_all<C: ConnectionTrait>(db: &C, user_id: i32) -> Result<(), DbErr> {
+    revoke_all_except(db, user_id, None).await
+}
+
+/// Ends every open session of `user_id` but `keep`, e.g. the one the
+/// request was made with.
+pub async fn revoke_all_except<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+    keep: Option<i64>,
+) -> Result<(), DbErr> {
+    let mut update = session::Entity::update_many()
+        .col_expr(
+            session::Column::RevokedAt,
+            Expr::value(chrono::Utc::now().naive_utc()),
+        )
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::RevokedAt.is_null());
+    if let Some(keep) = keep {
+        update = update.filter(session::Column::Id.ne(keep));
+    }
+    update.exec(db).await?;
+    Ok(())
+}
+
+/// Rejects a sign-in a browser makes on behalf of another site, which
+/// would sign the victim into the attacker's account (login CSRF). Pages
+/// on the site itself and on `trusted` origins may sign in, as may clients
+/// that send neither `Sec-Fetch-Site` nor `Origin`, which aren't browsers.
+pub fn check_origin(headers: &HeaderMap, trusted: &[String]) -> Result<(), AppError> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let origin = header("origin");
+
+    let allowed = origin.is_some_and(|origin| trusted.iter().any(|t| t == origin))
+        || match header("sec-fetch-site") {
+            Some(site) => site == "same-origin" || site == "none",
+            // Browsers without Fetch Metadata still send `Origin` on POST
+            None => origin.is_none_or(|origin| {
+                let host = origin.split_once("://").map(|(_, host)| host);
+                host.is_some() && host == header("host")
+            }),
+        };
+
+    if allowed {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden(
+            "cross-site sign-in requests are not allowed".to_string(),
+        ))
+    }
+}
+
+/// Value of cookie `name` in the request, if sent.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+fn set_cookie(
+    config: &SessionConfig,
+    name: &str,
+    value: &str,
+    max_age: u64,
+    http_only: bool,
+) -> HeaderValue {
+    let mut cookie = format!("{name}={value}; Path=/; Max-Age={max_age}");
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if config.cookie_secure {
+        cookie.push_str("; Secure");
+    }
+    cookie.push_str(match config.same_site {
+        SameSite::Strict => "; SameSite=Strict",
+        SameSite::Lax => "; SameSite=Lax",
+    });
+    // Names are validated with the config and values are base64url
+    HeaderValue::from_str(&cookie).expect("cookie is a valid header value")
+}
+
+/// `Set-Cookie` values starting `session` in the browser.
+pub fn cookies(config: &SessionConfig, session: &NewSession) -> [HeaderValue; 2] {
+    let max_age = config.absolute_timeout.as_secs();
+    [
+        set_cookie(config, &config.cookie_name, &session.token, max_age, true),
+        set_cookie(config, CSRF_COOKIE, &session.csrf_token, max_age, false),
+    ]
+}
+
+/// `Set-Cookie` values removing the session cookies from the browser.
+pub fn clear_cookies(config: &SessionConfig) -> [HeaderValue; 2] {
+    [
+        set_cookie(config, &config.cookie_name, "", 0, true),
+        set_cookie(config, CSRF_COOKIE, "", 0, false),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    header::HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    fn config() -> SessionConfig {
+        crate::config::Config::default().session
+    }
+
+    fn trusted() -> Vec<String> {
+        vec!["https://app.example.com".to_string()]
+    }
+
+    #[test]
+    fn same_origin_sign_ins_are_allowed() {
+        let same_origin = headers(&[
+            ("origin", "https://api.example.com"),
+            ("sec-fetch-site", "same-origin"),
+        ]);
+        assert!(check_origin(&same_origin, &trusted()).is_ok());
+
+        let typed_in = headers(&[("sec-fetch-site", "none")]);
+        assert!(check_origin(&typed_in, &trusted()).is_ok());
+    }
+
+    #[test]
+    fn cross_site_sign_ins_are_rejected() {
+        for site in ["cross-site", "same-site"] {
+            let request = headers(&[("origin", "https://evil.example"), ("sec-fetch-site", site)]);
+            assert!(check_origin(&request, &trusted()).is_err(), "{site}");
+        }
+    }
+
+    #[test]
+    fn trusted_origins_may_sign_in_cross_site() {
+        let request = headers(&[
+            ("origin", "https://app.example.com"),
+            ("sec-fetch-site", "cross-site"),
+        ]);
+        assert!(check_origin(&request, &trusted()).is_ok());
+    }
+
+    #[test]
+    fn origin_is_compared_with_host_without_fetch_metadata() {
+        let same = headers(&[
+            ("origin", "http://localhost:3000"),
+            ("host", "localhost:3000"),
+        ]);
+        assert!(check_origin(&same, &[]).is_ok());
+
+        let other = headers(&[
+            ("origin", "http://evil.example"),
+            ("host", "localhost:3000"),
+        ]);
+        assert!(check_origin(&other, &[]).is_err());
+
+        let opaque = headers(&[("origin", "null"), ("host", "localhost:3000")]);
+        assert!(check_origin(&opaque, &[]).is_err());
+    }
+
+    #[test]
+    fn clients_without_browser_headers_may_sign_in() {
+        assert!(check_origin(&HeaderMap::new(), &[]).is_ok());
+    }
+
+    #[test]
+    fn cookies_are_found_by_name_across_headers() {
+        let mut request = headers(&[("cookie", "theme=dark; session=abc")]);
+        request.append(header::COOKIE, HeaderValue::from_static("csrf_token=def"));
+
+        assert_eq!(cookie(&request, "session"), Some("abc"));
+        assert_eq!(cookie(&request, "csrf_token"), Some("def"));
+        assert_eq!(cookie(&request, "sess"), None);
+        assert_eq!(cookie(&HeaderMap::new(), "session"), None);
+    }
+
+    #[test]
+    fn session_cookie_is_http_only_and_csrf_cookie_is_readable() {
+        let session = NewSession {
+            id: 1,
+            token: "tok".to_string(),
+            csrf_token: "csrf".to_string(),
+            expires_at: chrono::Utc::now().naive_utc(),
+        };
+        let config = config();
+        let max_age = config.absolute_timeout.as_secs();
+
+        let [session_cookie, csrf_cookie] = cookies(&config, &session);
+
+        assert_eq!(
+            session_cookie,
+            format!(
+                "{}=tok; Path=/; Max-Age={max_age}; HttpOnly; Secure; SameSite=Lax",
+                config.cookie_name
+            )
+        );
+        assert_eq!(
+            csrf_cookie,
+            format!("csrf_token=csrf; Path=/; Max-Age={max_age}; Secure; SameSite=Lax")
+        );
+    }
+
+    #[test]
+    fn cookie_attributes_follow_the_config() {
+        let mut config = config();
+        config.cookie_secure = false;
+        config.same_site = SameSite::Strict;
+
+        let cookie = set_cookie(&config, "name", "value", 60, false);
+
+        assert_eq!(cookie, "name=value; Path=/; Max-Age=60; SameSite=Strict");
+    }
+
+    #[test]
+    fn clearing_expires_both_cookies() {
+        for cookie in clear_cookies(&config()) {
+            assert!(cookie.to_str().unwrap().contains("=; Path=/; Max-Age=0"));
+        }
+    }
+}
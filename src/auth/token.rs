@@ -0,0 +1,20 @@
+//! Random opaque tokens, stored only as hashes.
+//!
+//! Tokens carry 256 bits of randomness, so a plain SHA-256 is enough to
+//! make a leaked table useless; a slow password hash would only add latency.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// 32 random bytes, URL-safe base64 encoded (43 characters).
+pub fn generate() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The value stored in place of `token`.
+pub fn hash(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
@@ -0,0 +1,515 @@
+//! Two-factor authentication with time-based one-time passwords (RFC 6238).
+//!
+//! Users enroll by adding a generated secret to an authenticator app and
+//! confirming it with a first code. From then on, a correct password only
+//! earns a short-lived challenge token; a current code, or one of the
+//! recovery codes handed out at confirmation, completes the sign-in.
+//!
+//! Admins can require two-factor authentication per role. Users of such a
+//! role who haven't enrolled are refused everywhere but enrollment.
+
+use chrono::{DateTime, Utc};
+use rand::{rngs::OsRng, RngCore};
+use ring::hmac;
+use sea_orm::{
+    sea_query::OnConflict, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection,
+    DbErr, EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{constant_time_eq, jwt::Jwt, token};
+use crate::{
+    db::Traced,
+    entities::{
+        mfa_required_role, recovery_code, totp_credential,
+        user::{self, Role},
+    },
+    error::AppError,
+};
+
+const DIGITS: u32 = 6;
+const PERIOD_SECS: i64 = 30;
+
+/// Codes this many steps early or late are accepted, for clock drift.
+const SKEW_STEPS: i64 = 1;
+
+/// 160 bits, the HMAC-SHA1 output size RFC 4226 recommends.
+const SECRET_LENGTH: usize = 20;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Random bytes per recovery code; 80 bits make 16 base32 characters.
+const RECOVERY_CODE_BYTES: usize = 10;
+
+/// Wrong codes in a row before further attempts are refused for
+/// [`LOCKOUT_SECS`]. Six digits don't survive unlimited guessing.
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+const LOCKOUT_SECS: i64 = 5 * 60;
+
+/// `aud` claim of challenge tokens.
+const CHALLENGE_AUDIENCE: &str = "mfa-challenge";
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// RFC 4648 base32 without padding, the encoding authenticator apps expect.
+fn base32(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8 | u32::from(byte)) & 0xffff;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[(buffer >> bits & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[(buffer << (5 - bits) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Escapes everything but RFC 3986 unreserved characters.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// The HOTP value (RFC 4226) of `secret` for `counter`.
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let digest = hmac::sign(&key, &counter.to_be_bytes());
+    let digest = digest.as_ref();
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let truncated = u32::from_be_bytes([
+        digest[offset],
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]) & 0x7fff_ffff;
+    truncated % 10u32.pow(digits)
+}
+
+fn time_step(now: DateTime<Utc>) -> i64 {
+    now.timestamp().div_euclid(PERIOD_SECS)
+}
+
+/// The time step `code` is valid for, within [`SKEW_STEPS`] of `now` and
+/// later than `last_used`.
+fn matching_step(secret: &[u8], code: &str, now: i64, last_used: Option<i64>) -> Option<i64> {
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    (now - SKEW_STEPS..=now + SKEW_STEPS)
+        .filter(|&step| step >= 0 && last_used.is_none_or(|last| step > last))
+        .find(|&step| {
+            let expected = hotp(secret, step as u64, DIGITS);
+            constant_time_eq(&format!("{expected:06}"), code)
+        })
+}
+
+/// The URI authenticator apps import, usually scanned as a QR code.
+fn otpauth_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    let issuer = percent_encode(issuer);
+    format!(
+        "otpauth://totp/{issuer}:{}?secret={}&issuer={issuer}\
+         &algorithm=SHA1&digits={DIGITS}&period={PERIOD_SECS}",
+        percent_encode(account),
+        base32(secret),
+    )
+}
+
+/// A fresh recovery code, formatted `XXXX-XXXX-XXXX-XXXX`.
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    base32(&bytes)
+        .as_bytes()
+        .chunks(4)
+        .map(|chunk| std::str::from_utf8(chunk).expect("base32 is ASCII"))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Drops separators and case, so codes can be typed however they're read.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn invalid_code() -> AppError {
+    AppError::BadRequest("two-factor code is incorrect or already used".to_string())
+}
+
+/// A secret being enrolled, for the user to add to their authenticator app.
+#[derive(Serialize)]
+pub struct Enrollment {
+    /// Base32, for typing in by hand.
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// Generates a secret for `user`, replacing one that was never confirmed.
+pub async fn enroll<C: ConnectionTrait>(
+    db: &C,
+    issuer: &str,
+    user: &user::Model,
+) -> Result<Enrollment, AppError> {
+    let existing = totp_credential::Entity::find_by_id(user.id)
+        .lock_exclusive()
+        .one(db)
+        .await?;
+    match existing {
+        Some(existing) if existing.confirmed_at.is_some() => {
+            return Err(AppError::BadRequest(
+                "two-factor authentication is already enabled".to_string(),
+            ))
+        }
+        Some(_) => {
+            totp_credential::Entity::delete_by_id(user.id)
+                .exec(db)
+                .await?;
+        }
+        None => {}
+    }
+
+    let mut secret = vec![0u8; SECRET_LENGTH];
+    OsRng.fill_bytes(&mut secret);
+
+    totp_credential::ActiveModel {
+        user_id: Set(user.id),
+        secret: Set(secret.clone()),
+        created_at: Set(chrono::Utc::now().naive_utc()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok(Enrollment {
+        secret: base32(&secret),
+        otpauth_uri: otpauth_uri(issuer, &user.email, &secret),
+    })
+}
+
+/// Enables two-factor authentication once `code` shows the app has the
+/// secret. Returns new recovery codes, replacing any earlier ones; only
+/// their hashes are kept.
+pub async fn confirm<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+    code: &str,
+) -> Result<Vec<String>, AppError> {
+    let credential = totp_credential::Entity::find_by_id(user_id)
+        .lock_exclusive()
+        .one(db)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("start two-factor enrollment first".to_string()))?;
+    if credential.confirmed_at.is_some() {
+        return Err(AppError::BadRequest(
+            "two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    let now = chrono::Utc::now();
+    let step =
+        matching_step(&credential.secret, code, time_step(now), None).ok_or_else(invalid_code)?;
+
+    let mut credential = credential.into_active_model();
+    credential.confirmed_at = Set(Some(now.naive_utc()));
+    credential.last_used_step = Set(Some(step));
+    credential.update(db).await?;
+
+    recovery_code::Entity::delete_many()
+        .filter(recovery_code::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    recovery_code::Entity::insert_many(codes.iter().map(|code| recovery_code::ActiveModel {
+        user_id: Set(user_id),
+        code_hash: Set(token::hash(&normalize_recovery_code(code))),
+        created_at: Set(now.naive_utc()),
+        ..Default::default()
+    }))
+    .exec(db)
+    .await?;
+
+    Ok(codes)
+}
+
+/// Whether `user_id` has confirmed two-factor authentication.
+pub async fn is_enabled<C: ConnectionTrait>(db: &C, user_id: i32) -> Result<bool, DbErr> {
+    let count = totp_credential::Entity::find_by_id(user_id)
+        .filter(totp_credential::Column::ConfirmedAt.is_not_null())
+        .count(db)
+        .await?;
+    Ok(count > 0)
+}
+
+/// Turns two-factor authentication off for `user_id`, e.g. after a lost
+/// device. Returns `false` if it wasn't set up.
+pub async fn disable<C: ConnectionTrait>(db: &C, user_id: i32) -> Result<bool, DbErr> {
+    recovery_code::Entity::delete_many()
+        .filter(recovery_code::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+    let result = totp_credential::Entity::delete_by_id(user_id)
+        .exec(db)
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+/// Checks a TOTP or recovery code of `user_id`, spending it if correct.
+/// Wrong codes count towards a temporary lockout.
+pub async fn check(
+    db: &Traced<DatabaseConnection>,
+    user_id: i32,
+    code: &str,
+) -> Result<(), AppError> {
+    let txn = db.begin().await?;
+
+    let credential = totp_credential::Entity::find_by_id(user_id)
+        .filter(totp_credential::Column::ConfirmedAt.is_not_null())
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+        .ok_or_else(invalid_code)?;
+
+    let now = chrono::Utc::now();
+    let lockout_start = now.naive_utc() - chrono::Duration::seconds(LOCKOUT_SECS);
+    let recent_failure = credential.last_failed_at.is_some_and(|t| t > lockout_start);
+    if recent_failure && credential.failed_attempts >= MAX_FAILED_ATTEMPTS {
+        return Err(AppError::Rejection {
+            status: axum::http::StatusCode::TOO_MANY_REQUESTS,
+            code: "too_many_attempts",
+            detail: "too many incorrect two-factor codes; try again later".to_string(),
+        });
+    }
+
+    let step = matching_step(
+        &credential.secret,
+        code.trim(),
+        time_step(now),
+        credential.last_used_step,
+    );
+    let accepted = step.is_some() || redeem_recovery_code(&txn, user_id, code).await?;
+
+    let failed_attempts = credential.failed_attempts;
+    let mut credential = credential.into_active_model();
+    if accepted {
+        if step.is_some() {
+            credential.last_used_step = Set(step);
+        }
+        credential.failed_attempts = Set(0);
+        credential.last_failed_at = Set(None);
+    } else {
+        credential.failed_attempts = Set(if recent_failure {
+            failed_attempts + 1
+        } else {
+            1
+        });
+        credential.last_failed_at = Set(Some(now.naive_utc()));
+    }
+    credential.update(&txn).await?;
+
+    // Failures are committed too, so they count
+    txn.commit().await?;
+
+    if accepted {
+        Ok(())
+    } else {
+        tracing::warn!(user_id, "incorrect two-factor code");
+        Err(invalid_code())
+    }
+}
+
+async fn redeem_recovery_code<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+    code: &str,
+) -> Result<bool, DbErr> {
+    let code = normalize_recovery_code(code);
+    if code.len() != RECOVERY_CODE_BYTES * 8 / 5 {
+        return Ok(false);
+    }
+    let result = recovery_code::Entity::update_many()
+        .col_expr(
+            recovery_code::Column::UsedAt,
+            sea_orm::sea_query::Expr::value(chrono::Utc::now().naive_utc()),
+        )
+        .filter(recovery_code::Column::UserId.eq(user_id))
+        .filter(recovery_code::Column::CodeHash.eq(token::hash(&code)))
+        .filter(recovery_code::Column::UsedAt.is_null())
+        .exec(db)
+        .await?;
+    if result.rows_affected > 0 {
+        tracing::info!(user_id, "used a recovery code");
+    }
+    Ok(result.rows_affected > 0)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Challenge {}
+
+/// Signs the token that proves the password step of `user_id`'s sign-in.
+pub fn challenge(jwt: &Jwt, user_id: i32, ttl: std::time::Duration) -> Result<String, AppError> {
+    jwt.issue_for(CHALLENGE_AUDIENCE, user_id, ttl, Challenge {})
+        .map_err(|e| AppError::Internal(format!("failed to sign two-factor challenge: {e}")))
+}
+
+/// The user a challenge token from [`challenge`] was issued to.
+pub fn verify_challenge(jwt: &Jwt, token: &str) -> Option<i32> {
+    jwt.verify_for::<Challenge>(CHALLENGE_AUDIENCE, token)
+        .map(|(user_id, _)| user_id)
+}
+
+/// Roles whose users must enable two-factor authentication.
+pub async fn required_roles<C: ConnectionTrait>(db: &C) -> Result<Vec<Role>, DbErr> {
+    Ok(mfa_required_role::Entity::find()
+        .order_by_asc(mfa_required_role::Column::Role)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|r| r.role)
+        .collect())
+}
+
+pub async fn set_required<C: ConnectionTrait>(
+    db: &C,
+    role: Role,
+    required: bool,
+) -> Result<(), DbErr> {
+    if required {
+        mfa_required_role::Entity::insert(mfa_required_role::ActiveModel {
+            role: Set(role),
+            created_at: Set(chrono::Utc::now().naive_utc()),
+        })
+        .on_conflict(
+            OnConflict::column(mfa_required_role::Column::Role)
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await?;
+    } else {
+        mfa_required_role::Entity::delete_by_id(role)
+            .exec(db)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Refuses `user_id` with `403` if `role` requires two-factor
+/// authentication and the user hasn't enabled it.
+pub async fn require_enrolled<C: ConnectionTrait>(
+    db: &C,
+    user_id: i32,
+    role: Role,
+) -> Result<(), AppError> {
+    let required = mfa_required_role::Entity::find_by_id(role)
+        .count(db)
+        .await?
+        > 0;
+    if required && !is_enabled(db, user_id).await? {
+        return Err(AppError::Forbidden(format!(
+            "your role requires two-factor authentication; enable it with POST /users/{user_id}/totp"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The RFC 4226 and RFC 6238 test secret.
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn base32_matches_rfc_4648() {
+        assert_eq!(base32(b""), "");
+        assert_eq!(base32(b"f"), "MY");
+        assert_eq!(base32(b"fo"), "MZXQ");
+        assert_eq!(base32(b"foo"), "MZXW6");
+        assert_eq!(base32(b"foob"), "MZXW6YQ");
+        assert_eq!(base32(b"fooba"), "MZXW6YTB");
+        assert_eq!(base32(b"foobar"), "MZXW6YTBOI");
+    }
+
+    #[test]
+    fn hotp_matches_rfc_4226() {
+        let expected = [755224, 287082, 359152, 969429, 338314];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(SECRET, counter as u64, 6), code);
+        }
+    }
+
+    #[test]
+    fn totp_matches_rfc_6238() {
+        for (time, code) in [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (2000000000, 69279037),
+        ] {
+            let now = DateTime::from_timestamp(time, 0).unwrap();
+            assert_eq!(hotp(SECRET, time_step(now) as u64, 8), code);
+        }
+    }
+
+    #[test]
+    fn codes_are_accepted_within_the_skew_only() {
+        let now = 1000;
+        let code = |step| format!("{:06}", hotp(SECRET, step as u64, DIGITS));
+        assert_eq!(matching_step(SECRET, &code(now), now, None), Some(now));
+        assert_eq!(
+            matching_step(SECRET, &code(now - 1), now, None),
+            Some(now - 1)
+        );
+        assert_eq!(
+            matching_step(SECRET, &code(now + 1), now, None),
+            Some(now + 1)
+        );
+        assert_eq!(matching_step(SECRET, &code(now - 2), now, None), None);
+        assert_eq!(matching_step(SECRET, "12345", now, None), None);
+        assert_eq!(matching_step(SECRET, "abcdef", now, None), None);
+    }
+
+    #[test]
+    fn codes_cannot_be_replayed() {
+        let now = 1000;
+        let code = format!("{:06}", hotp(SECRET, now as u64, DIGITS));
+        assert_eq!(matching_step(SECRET, &code, now, Some(now)), None);
+        assert_eq!(matching_step(SECRET, &code, now, Some(now - 1)), Some(now));
+    }
+
+    #[test]
+    fn otpauth_uri_escapes_labels() {
+        assert_eq!(
+            otpauth_uri("My App", "jane+2fa@example.com", b"foobar"),
+            "otpauth://totp/My%20App:jane%2B2fa%40example.com?secret=MZXW6YTBOI\
+             &issuer=My%20App&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn recovery_codes_normalize_to_their_base32_form() {
+        let code = generate_recovery_code();
+        assert_eq!(code.len(), 19);
+        assert_eq!(code.matches('-').count(), 3);
+        let normalized = normalize_recovery_code(&code);
+        assert_eq!(normalized.len(), RECOVERY_CODE_BYTES * 8 / 5);
+        assert_eq!(normalize_recovery_code(&code.to_lowercase()), normalized);
+        assert_eq!(normalize_recovery_code(" abcd-efgh "), "ABCDEFGH");
+    }
+}
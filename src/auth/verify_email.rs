@@ -0,0 +1,87 @@
+//! Email verification links.
+//!
+//! The link carries a signed token naming the user, the address it was
+//! sent to and a nonce stored on the user. It confirms that address only
+//! while it is still the user's email and the nonce is the latest one, so
+//! it works once and stops working when a newer link is sent or the email
+//! changes, even if it later changes back.
+
+use std::time::Duration;
+
+use sea_orm::{sea_query::Expr, ColumnTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+
+use super::token;
+use crate::{
+    entities::user,
+    mail::{self, Message},
+    AppState,
+};
+
+/// `aud` claim of verification tokens.
+const AUDIENCE: &str = "verify-email";
+
+/// Minimum time between two links a user asks to have resent.
+pub const RESEND_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize)]
+pub struct Claims {
+    pub email: String,
+    /// Must match the user's `email_verification_nonce`.
+    pub nonce: String,
+}
+
+/// Emails `user` a link confirming their current address, replacing any
+/// link sent before. Failures are logged rather than returned: the user can
+/// ask for another link.
+pub async fn send(state: &AppState, user: &user::Model) {
+    let nonce = token::generate();
+    // Not a user-visible change, so `version` and `updated_at` stay as is
+    let stored = user::Entity::update_many()
+        .col_expr(user::Column::EmailVerificationNonce, Expr::value(&nonce))
+        .filter(user::Column::Id.eq(user.id))
+        .exec(&state.db)
+        .await;
+    if let Err(e) = stored {
+        tracing::error!(user_id = user.id, "failed to store verification nonce: {e}");
+        return;
+    }
+
+    let claims = Claims {
+        email: user.email.clone(),
+        nonce,
+    };
+    let token = match state
+        .jwt
+        .issue_for(AUDIENCE, user.id, state.email_verification_ttl, claims)
+    {
+        Ok(token) => token,
+        Err(e) => {
+            tracing::error!(user_id = user.id, "failed to sign verification token: {e}");
+            return;
+        }
+    };
+
+    let message = Message {
+        to: user.email.clone(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Hi {},\n\nOpen this link to confirm your email address:\n\n{}\n\n\
+             The link expires in {} hours.\n",
+            user.name,
+            mail::link(&state.verify_email_url, &token),
+            state.email_verification_ttl.as_secs().div_ceil(3600),
+        ),
+    };
+
+    match state.mailer.send(message).await {
+        Ok(()) => tracing::info!(user_id = user.id, "sent verification email"),
+        Err(e) => tracing::error!(user_id = user.id, "failed to send verification email: {e}"),
+    }
+}
+
+/// The user ID and claims a verification token was issued with, if it is
+/// genuine and unexpired. Whether it is still current is up to the caller.
+pub fn verify(state: &AppState, token: &str) -> Option<(i32, Claims)> {
+    state.jwt.verify_for::<Claims>(AUDIENCE, token)
+}
@@ -0,0 +1,85 @@
+//! Bounded background work that graceful shutdown waits for.
+
+use std::{future::Future, sync::Arc};
+
+use tokio::sync::Semaphore;
+use tracing::Instrument;
+
+/// Runs fire-and-forget tasks, such as sending mail after a request has
+/// been answered, at most `capacity` at a time.
+#[derive(Clone)]
+pub struct Background {
+    permits: Arc<Semaphore>,
+    capacity: u32,
+}
+
+impl Background {
+    pub fn new(capacity: u32) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(capacity as usize)),
+            capacity,
+        }
+    }
+
+    /// Starts `task` in the current span, unless `capacity` tasks are
+    /// already running; then it's dropped and `false` is returned.
+    pub fn spawn<F>(&self, task: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let Ok(permit) = self.permits.clone().try_acquire_owned() else {
+            return false;
+        };
+        tokio::spawn(
+            async move {
+                task.await;
+                drop(permit);
+            }
+            .in_current_span(),
+        );
+        true
+    }
+
+    /// Completes once every task started so far has finished.
+    pub async fn drain(&self) {
+        let _ = self.permits.acquire_many(self.capacity).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn tasks_beyond_capacity_are_dropped() {
+        let background = Background::new(1);
+        let (release, released) = oneshot::channel::<()>();
+
+        assert!(background.spawn(async {
+            let _ = released.await;
+        }));
+        assert!(!background.spawn(async {}));
+
+        release.send(()).unwrap();
+        background.drain().await;
+    }
+
+    #[tokio::test]
+    async fn drain_waits_for_running_tasks() {
+        let background = Background::new(2);
+        let (release, released) = oneshot::channel::<()>();
+        background.spawn(async {
+            let _ = released.await;
+        });
+
+        let drained = tokio::time::timeout(Duration::from_millis(20), background.drain()).await;
+        assert!(drained.is_err());
+
+        release.send(()).unwrap();
+        background.drain().await;
+    }
+}
@@ -0,0 +1,209 @@
+//! Database pool setup.
+
+use std::time::Duration;
+
+use sea_orm::{
+    prelude::async_trait::async_trait, sqlx, ConnectOptions, ConnectionTrait, Database,
+    DatabaseConnection, DatabaseTransaction, DbBackend, DbErr, ExecResult, QueryResult, RuntimeErr,
+    Statement, TransactionTrait,
+};
+use tokio::time::{sleep, Instant};
+use tracing::{field::Empty, Instrument, Span};
+
+use crate::config::DatabaseConfig;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+fn connect_options(config: &DatabaseConfig) -> ConnectOptions {
+    let mut options = ConnectOptions::new(&config.url);
+    options
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .connect_timeout(config.connect_timeout)
+        .acquire_timeout(config.acquire_timeout)
+        .idle_timeout(config.idle_timeout);
+
+    // Slow-statement logging only works with SQLx logging on, so keep it
+    // enabled but silence regular statements unless they were asked for.
+    let statement_level = if config.log_statements {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Off
+    };
+    let slow_level = match config.slow_statement_threshold {
+        Some(_) => log::LevelFilter::Warn,
+        None => log::LevelFilter::Off,
+    };
+    options
+        .sqlx_logging(config.log_statements || config.slow_statement_threshold.is_some())
+        .sqlx_logging_level(statement_level)
+        .sqlx_slow_statements_logging_settings(
+            slow_level,
+            config.slow_statement_threshold.unwrap_or_default(),
+        );
+
+    options
+}
+
+/// Log filter directive needed for the SQLx statement logs to be emitted.
+pub fn log_directive(config: &DatabaseConfig) -> Option<&'static str> {
+    if config.log_statements {
+        Some("sqlx::query=debug")
+    } else if config.slow_statement_threshold.is_some() {
+        Some("sqlx::query=warn")
+    } else {
+        None
+    }
+}
+
+/// Errors worth retrying: the server is unreachable or still starting up.
+/// Bad credentials or an invalid URL fail immediately.
+fn is_transient(err: &DbErr) -> bool {
+    let (DbErr::Conn(RuntimeErr::SqlxError(err)) | DbErr::Exec(RuntimeErr::SqlxError(err))) = err
+    else {
+        return false;
+    };
+
+    match err {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::Tls(_) => true,
+        // 57P03 cannot_connect_now: "the database system is starting up"
+        sqlx::Error::Database(e) => e.code().as_deref() == Some("57P03"),
+        _ => false,
+    }
+}
+
+/// Connects to the database, retrying transient failures with exponential
+/// backoff until `connect_max_wait` has elapsed.
+pub async fn connect(config: &DatabaseConfig) -> Result<DatabaseConnection, DbErr> {
+    let options = connect_options(config);
+    let deadline = Instant::now() + config.connect_max_wait;
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        match Database::connect(options.clone()).await {
+            Ok(db) => return Ok(db),
+            Err(err) if is_transient(&err) && Instant::now() + backoff < deadline => {
+                tracing::warn!(
+                    error = %err,
+                    retry_in_ms = backoff.as_millis() as u64,
+                    "Database not reachable yet, retrying"
+                );
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// SQL verb and target table of a statement, e.g. `("SELECT", Some("users"))`,
+/// for labelling metrics and spans without recording the full query.
+pub fn describe(sql: &str) -> (String, Option<&str>) {
+    let mut words = sql.split_whitespace();
+    let operation = words.next().unwrap_or_default().to_uppercase();
+
+    let table = match operation.as_str() {
+        "UPDATE" => words.next(),
+        "SELECT" | "DELETE" => words.skip_while(|w| !w.eq_ignore_ascii_case("FROM")).nth(1),
+        "INSERT" => words.skip_while(|w| !w.eq_ignore_ascii_case("INTO")).nth(1),
+        _ => None,
+    };
+    let table = table
+        .map(|t| t.trim_matches('"'))
+        .filter(|t| !t.is_empty() && t.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+
+    (operation, table)
+}
+
+/// A connection or transaction that runs every statement in a `db.query`
+/// span tagged with the SQL operation and table, so queries show up as
+/// children of the request span in exported traces.
+#[derive(Clone)]
+pub struct Traced<C>(pub C);
+
+impl<C> std::ops::Deref for Traced<C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.0
+    }
+}
+
+impl Traced<DatabaseConnection> {
+    pub async fn begin(&self) -> Result<Traced<DatabaseTransaction>, DbErr> {
+        self.0.begin().await.map(Traced)
+    }
+}
+
+impl Traced<DatabaseTransaction> {
+    pub async fn commit(self) -> Result<(), DbErr> {
+        self.0.commit().await
+    }
+}
+
+fn query_span(sql: &str) -> Span {
+    let (operation, table) = describe(sql);
+    let name = match table {
+        Some(table) => format!("{operation} {table}"),
+        None => operation.clone(),
+    };
+    tracing::info_span!(
+        "db.query",
+        otel.name = name,
+        otel.kind = "client",
+        otel.status_code = Empty,
+        db.system = "postgresql",
+        db.operation = operation,
+        db.sql.table = table,
+    )
+}
+
+fn record_outcome<T>(span: &Span, result: &Result<T, DbErr>) {
+    if result.is_err() {
+        span.record("otel.status_code", "ERROR");
+    }
+}
+
+#[async_trait]
+impl<C: ConnectionTrait + Send> ConnectionTrait for Traced<C> {
+    fn get_database_backend(&self) -> DbBackend {
+        self.0.get_database_backend()
+    }
+
+    async fn execute(&self, stmt: Statement) -> Result<ExecResult, DbErr> {
+        let span = query_span(&stmt.sql);
+        let result = self.0.execute(stmt).instrument(span.clone()).await;
+        record_outcome(&span, &result);
+        result
+    }
+
+    async fn execute_unprepared(&self, sql: &str) -> Result<ExecResult, DbErr> {
+        let span = query_span(sql);
+        let result = self
+            .0
+            .execute_unprepared(sql)
+            .instrument(span.clone())
+            .await;
+        record_outcome(&span, &result);
+        result
+    }
+
+    async fn query_one(&self, stmt: Statement) -> Result<Option<QueryResult>, DbErr> {
+        let span = query_span(&stmt.sql);
+        let result = self.0.query_one(stmt).instrument(span.clone()).await;
+        record_outcome(&span, &result);
+        result
+    }
+
+    async fn query_all(&self, stmt: Statement) -> Result<Vec<QueryResult>, DbErr> {
+        let span = query_span(&stmt.sql);
+        let result = self.0.query_all(stmt).instrument(span.clone()).await;
+        record_outcome(&span, &result);
+        result
+    }
+
+    fn support_returning(&self) -> bool {
+        self.0.support_returning()
+    }
+}
@@ -0,0 +1,41 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "api_keys")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    /// The key acts as this user, with their role.
+    pub user_id: i32,
+    pub name: String,
+    /// Start of the key, shown in listings to tell keys apart.
+    pub prefix: String,
+    /// SHA-256 of the key; the key itself is only shown once, on creation.
+    pub key_hash: Vec<u8>,
+    /// `auth::api_key::Scope` names the key is limited to.
+    pub scopes: Vec<String>,
+    pub created_at: DateTime,
+    pub expires_at: Option<DateTime>,
+    /// Updated at most once a minute.
+    pub last_used_at: Option<DateTime>,
+    pub revoked_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
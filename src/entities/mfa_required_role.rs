@@ -0,0 +1,17 @@
+use sea_orm::entity::prelude::*;
+
+use super::user::Role;
+
+/// A role whose users must enable two-factor authentication.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "mfa_required_roles")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub role: Role,
+    pub created_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,2 +1,8 @@
+pub mod api_key;
+pub mod mfa_required_role;
+pub mod password_reset_token;
+pub mod recovery_code;
+pub mod refresh_token;
+pub mod session;
+pub mod totp_credential;
 pub mod user;
-
@@ -0,0 +1,40 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "sessions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub user_id: i32,
+    /// SHA-256 of the session cookie's value.
+    pub token_hash: Vec<u8>,
+    /// SHA-256 of the token state-changing requests must echo in `X-CSRF-Token`.
+    pub csrf_hash: Vec<u8>,
+    /// Shown when listing sessions, to help users recognize them.
+    pub user_agent: Option<String>,
+    pub created_at: DateTime,
+    /// Updated at most once a minute; drives the idle timeout.
+    pub last_seen_at: DateTime,
+    /// Absolute end of the session.
+    pub expires_at: DateTime,
+    pub revoked_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
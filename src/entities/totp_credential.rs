@@ -0,0 +1,38 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "totp_credentials")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user_id: i32,
+    /// Raw HMAC key shared with the authenticator app.
+    pub secret: Vec<u8>,
+    pub created_at: DateTime,
+    /// Set once the user proves their app has the secret; until then the
+    /// credential isn't required at sign-in.
+    pub confirmed_at: Option<DateTime>,
+    /// Time step of the last accepted code, so it can't be used twice.
+    pub last_used_step: Option<i64>,
+    /// Wrong codes in a row; reset by a correct one.
+    pub failed_attempts: i32,
+    pub last_failed_at: Option<DateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id",
+        on_delete = "Cascade"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
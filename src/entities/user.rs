@@ -1,6 +1,18 @@
+use std::sync::OnceLock;
+
 use sea_orm::entity::prelude::*;
+use sea_orm::{ActiveValue, Set};
 use serde::{Deserialize, Serialize};
 
+/// The `users.lowercase_email_local_part` setting, fixed at startup.
+static LOWERCASE_EMAIL_LOCAL_PART: OnceLock<bool> = OnceLock::new();
+
+/// Sets whether saved emails get a lowercased local part. Only the first
+/// call has an effect; until then local parts are kept as given.
+pub fn set_lowercase_email_local_part(enabled: bool) {
+    let _ = LOWERCASE_EMAIL_LOCAL_PART.set(enabled);
+}
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "users")]
 pub struct Model {
@@ -10,10 +22,122 @@ pub struct Model {
     pub email: String,
     pub created_at: DateTime,
     pub updated_at: DateTime,
+    /// Incremented on every update; backs the user's `ETag`.
+    pub version: i32,
+    /// Set when the user is soft-deleted; such rows are hidden from reads.
+    pub deleted_at: Option<DateTime>,
+    /// Argon2id hash; never serialized, so it can't leak into responses.
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    pub role: Role,
+    /// When the current `email` was confirmed; cleared when it changes.
+    pub email_verified_at: Option<DateTime>,
+    /// Ties verification links to the latest one sent; cleared once used.
+    #[serde(skip)]
+    pub email_verification_nonce: Option<String>,
+}
+
+/// What a user may do; see `auth::policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(StringLen::N(16))")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Manages every user.
+    #[sea_orm(string_value = "admin")]
+    Admin,
+    /// Reads and updates only their own record.
+    #[sea_orm(string_value = "member")]
+    Member,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {}
 
-impl ActiveModelBehavior for ActiveModel {}
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        if let ActiveValue::Set(email) = &self.email {
+            let lowercase_local_part = LOWERCASE_EMAIL_LOCAL_PART.get().copied().unwrap_or(false);
+            self.email = Set(normalize_email(email, lowercase_local_part));
+        }
+        if !insert {
+            if let Some(version) = self.version.try_as_ref() {
+                self.version = Set(version + 1);
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// Trims an email and lowercases its domain, and its local part too if
+/// `lowercase_local_part`. Applied to every email set on a user before it
+/// is saved, with the `users.lowercase_email_local_part` setting.
+///
+/// Uniqueness is enforced on `lower(email)` regardless, so this only
+/// controls how the address is stored and displayed.
+pub fn normalize_email(email: &str, lowercase_local_part: bool) -> String {
+    let email = email.trim();
+    let Some((local, domain)) = email.rsplit_once('@') else {
+        return email.to_string();
+    };
 
+    let local = if lowercase_local_part {
+        local.to_lowercase()
+    } else {
+        local.to_string()
+    };
+
+    format!("{local}@{}", domain.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domains_are_lowercased_and_whitespace_trimmed() {
+        assert_eq!(
+            normalize_email("  Jane.Doe@Example.COM ", false),
+            "Jane.Doe@example.com"
+        );
+    }
+
+    #[test]
+    fn local_parts_are_lowercased_when_asked() {
+        assert_eq!(
+            normalize_email("Jane.Doe@Example.COM", true),
+            "jane.doe@example.com"
+        );
+    }
+
+    #[test]
+    fn only_the_last_at_sign_splits_the_domain() {
+        assert_eq!(
+            normalize_email("\"a@b\"@Example.com", false),
+            "\"a@b\"@example.com"
+        );
+    }
+
+    #[test]
+    fn addresses_without_a_domain_are_only_trimmed() {
+        assert_eq!(normalize_email(" Jane ", true), "Jane");
+    }
+
+    #[tokio::test]
+    async fn emails_are_normalized_before_saving() {
+        let user = ActiveModel {
+            email: Set(" Jane.Doe@Example.COM".to_string()),
+            ..Default::default()
+        };
+
+        let user = user
+            .before_save(&DatabaseConnection::Disconnected, true)
+            .await
+            .unwrap();
+
+        assert_eq!(user.email, Set("Jane.Doe@example.com".to_string()));
+    }
+}
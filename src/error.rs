@@ -0,0 +1,506 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use json_patch::{PatchError, PatchErrorKind};
+use sea_orm::{
+    sqlx::{self, postgres::PgDatabaseError},
+    DbErr, RuntimeErr,
+};
+use serde::Serialize;
+use validator::ValidationErrors;
+
+use crate::request_id;
+
+/// Crate-wide error type returned by handlers.
+///
+/// Every variant is rendered as an RFC 7807 `application/problem+json` body
+/// with a stable `code`. Internal causes are logged, never sent to clients.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Unauthorized(String),
+    NotFound(String),
+    Forbidden(String),
+    PreconditionFailed(String),
+    Constraint(ConstraintViolation),
+    Validation(ValidationErrors),
+    UnsupportedMediaType(String),
+    Patch(PatchError),
+    Rejection {
+        status: StatusCode,
+        code: &'static str,
+        detail: String,
+    },
+    Database(DbErr),
+    /// Unexpected server-side failure; the message is logged, not returned.
+    Internal(String),
+}
+
+#[derive(Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+    /// Same as the `X-Request-Id` response header, to quote in bug reports.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// One entry in the `errors` member of a validation problem.
+#[derive(Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+/// Kinds of database failures we surface to clients, classified by SQLSTATE.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// `23505 unique_violation`
+    Unique,
+    /// `23503 foreign_key_violation`
+    ForeignKey,
+    /// `23502 not_null_violation`
+    NotNull,
+    /// `23514 check_violation`
+    Check,
+    /// `40001 serialization_failure` and `40P01 deadlock_detected`
+    SerializationFailure,
+}
+
+#[derive(Debug)]
+pub struct ConstraintViolation {
+    pub kind: ViolationKind,
+    pub field: Option<String>,
+}
+
+/// Maps constraint names to the request field they guard, for constraints
+/// whose name doesn't reveal the column (e.g. expression indexes).
+const CONSTRAINT_FIELDS: &[(&str, &str)] = &[
+    ("users_email_key", "email"),
+    ("idx_users_email_lower", "email"),
+];
+
+impl ViolationKind {
+    fn from_sqlstate(code: &str) -> Option<Self> {
+        match code {
+            "23505" => Some(Self::Unique),
+            "23503" => Some(Self::ForeignKey),
+            "23502" => Some(Self::NotNull),
+            "23514" => Some(Self::Check),
+            "40001" | "40P01" => Some(Self::SerializationFailure),
+            _ => None,
+        }
+    }
+}
+
+/// The request field behind a violation, from the error's column, its
+/// constraint name, or the key named in its detail message.
+fn violated_field(
+    column: Option<&str>,
+    constraint: Option<&str>,
+    detail: Option<&str>,
+) -> Option<String> {
+    if let Some(column) = column {
+        return Some(column.to_string());
+    }
+    if let Some(field) = constraint.and_then(|name| {
+        CONSTRAINT_FIELDS
+            .iter()
+            .find(|(constraint, _)| *constraint == name)
+            .map(|(_, field)| field.to_string())
+    }) {
+        return Some(field);
+    }
+    // Unique violations report "Key (email)=(...) already exists."
+    detail
+        .and_then(|detail| detail.strip_prefix("Key ("))
+        .and_then(|rest| rest.split_once(")="))
+        .map(|(column, _)| column)
+        .filter(|column| column.chars().all(|c| c.is_alphanumeric() || c == '_'))
+        .map(str::to_string)
+}
+
+impl ConstraintViolation {
+    /// Classifies a Postgres error by SQLSTATE, or `None` if it isn't one
+    /// of the kinds in [`ViolationKind`].
+    pub fn from_db_err(err: &DbErr) -> Option<Self> {
+        let (DbErr::Exec(RuntimeErr::SqlxError(sqlx::Error::Database(e)))
+        | DbErr::Query(RuntimeErr::SqlxError(sqlx::Error::Database(e)))) = err
+        else {
+            return None;
+        };
+
+        let kind = ViolationKind::from_sqlstate(e.code()?.as_ref())?;
+        let pg = e.try_downcast_ref::<PgDatabaseError>();
+
+        let field = pg.and_then(|pg| violated_field(pg.column(), pg.constraint(), pg.detail()));
+
+        Some(Self { kind, field })
+    }
+
+    fn status(&self) -> StatusCode {
+        match self.kind {
+            ViolationKind::Unique
+            | ViolationKind::ForeignKey
+            | ViolationKind::SerializationFailure => StatusCode::CONFLICT,
+            ViolationKind::NotNull | ViolationKind::Check => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self.kind {
+            ViolationKind::Unique => "already_exists",
+            ViolationKind::ForeignKey => "foreign_key_violation",
+            ViolationKind::NotNull => "missing_value",
+            ViolationKind::Check => "check_violation",
+            ViolationKind::SerializationFailure => "serialization_failure",
+        }
+    }
+
+    fn detail(&self) -> String {
+        let field = self.field.as_deref();
+        match (self.kind, field) {
+            (ViolationKind::Unique, Some(field)) => format!("{field} is already in use"),
+            (ViolationKind::Unique, None) => "resource already exists".to_string(),
+            (ViolationKind::ForeignKey, Some(field)) => {
+                format!("{field} references a missing or in-use resource")
+            }
+            (ViolationKind::ForeignKey, None) => {
+                "operation references a missing or in-use resource".to_string()
+            }
+            (ViolationKind::NotNull, Some(field)) => format!("{field} is required"),
+            (ViolationKind::NotNull, None) => "a required value is missing".to_string(),
+            (ViolationKind::Check, Some(field)) => format!("{field} has an invalid value"),
+            (ViolationKind::Check, None) => "a value is invalid".to_string(),
+            (ViolationKind::SerializationFailure, _) => {
+                "concurrent update detected, please retry".to_string()
+            }
+        }
+    }
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            Self::Constraint(violation) => violation.status(),
+            Self::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            // RFC 5789 suggests 409 when a patch can't apply to the current state.
+            Self::Patch(err) if matches!(err.kind, PatchErrorKind::TestFailed) => {
+                StatusCode::CONFLICT
+            }
+            Self::Patch(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Rejection { status, .. } => *status,
+            Self::Database(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::NotFound(_) => "not_found",
+            Self::Forbidden(_) => "forbidden",
+            Self::PreconditionFailed(_) => "precondition_failed",
+            Self::Constraint(violation) => violation.code(),
+            Self::Validation(_) => "validation_failed",
+            Self::UnsupportedMediaType(_) => "unsupported_media_type",
+            Self::Patch(err) if matches!(err.kind, PatchErrorKind::TestFailed) => {
+                "patch_test_failed"
+            }
+            Self::Patch(_) => "invalid_patch",
+            Self::Rejection { code, .. } => code,
+            Self::Database(_) | Self::Internal(_) => "internal_error",
+        }
+    }
+
+    fn detail(&self) -> Option<String> {
+        match self {
+            Self::BadRequest(detail)
+            | Self::Unauthorized(detail)
+            | Self::NotFound(detail)
+            | Self::Forbidden(detail)
+            | Self::PreconditionFailed(detail)
+            | Self::Rejection { detail, .. } => Some(detail.clone()),
+            Self::Constraint(violation) => Some(violation.detail()),
+            Self::Validation(_) => Some("request body failed validation".to_string()),
+            Self::UnsupportedMediaType(detail) => Some(detail.clone()),
+            Self::Patch(err) => Some(err.to_string()),
+            Self::Database(_) | Self::Internal(_) => None,
+        }
+    }
+
+    pub fn user_not_found(id: i32) -> Self {
+        Self::NotFound(format!("user {id} not found"))
+    }
+
+    /// A request body that isn't well-formed JSON of the expected shape.
+    pub fn invalid_body(err: serde_json::Error) -> Self {
+        Self::Rejection {
+            status: StatusCode::BAD_REQUEST,
+            code: "invalid_body",
+            detail: err.to_string(),
+        }
+    }
+
+    /// A patch that applied cleanly but produced an invalid document.
+    pub fn invalid_patch(detail: String) -> Self {
+        Self::Rejection {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            code: "invalid_patch",
+            detail,
+        }
+    }
+}
+
+impl From<DbErr> for AppError {
+    fn from(err: DbErr) -> Self {
+        match ConstraintViolation::from_db_err(&err) {
+            Some(violation) => {
+                tracing::debug!(error = %err, "constraint violation");
+                Self::Constraint(violation)
+            }
+            None => Self::Database(err),
+        }
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        Self::Validation(errors)
+    }
+}
+
+impl From<PatchError> for AppError {
+    fn from(err: PatchError) -> Self {
+        Self::Patch(err)
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::Rejection {
+            status: rejection.status(),
+            code: "invalid_body",
+            detail: rejection.body_text(),
+        }
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::Rejection {
+            status: rejection.status(),
+            code: "invalid_query",
+            detail: rejection.body_text(),
+        }
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::Rejection {
+            status: rejection.status(),
+            code: "invalid_path",
+            detail: rejection.body_text(),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+
+        match &self {
+            Self::Database(err) => tracing::error!(error = %err, "database error"),
+            Self::Internal(message) => tracing::error!("{message}"),
+            _ => {}
+        }
+
+        let problem = Problem {
+            kind: "about:blank",
+            title: status.canonical_reason().unwrap_or("Error"),
+            status: status.as_u16(),
+            code: self.code(),
+            detail: self.detail(),
+            field: match &self {
+                Self::Constraint(violation) => violation.field.clone(),
+                _ => None,
+            },
+            errors: match &self {
+                Self::Validation(errors) => field_errors(errors),
+                _ => Vec::new(),
+            },
+            request_id: request_id::current(),
+        };
+
+        let mut response = (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(problem),
+        )
+            .into_response();
+        // RFC 9110 requires 401 responses to name an auth scheme.
+        if status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Bearer"),
+            );
+        }
+        response
+    }
+}
+
+fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut fields: Vec<FieldError> = errors
+        .field_errors()
+        .into_iter()
+        .flat_map(|(field, errors)| {
+            errors.iter().map(move |error| FieldError {
+                field: field.to_string(),
+                code: error.code.to_string(),
+                message: error
+                    .message
+                    .as_ref()
+                    .map_or_else(|| error.code.to_string(), |m| m.to_string()),
+            })
+        })
+        .collect();
+    // `field_errors` is backed by a HashMap; keep the output deterministic.
+    fields.sort_by(|a, b| a.field.cmp(&b.field));
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use sea_orm::sqlx::error::{DatabaseError, ErrorKind};
+
+    use super::*;
+
+    /// A database error carrying only a SQLSTATE, as drivers other than
+    /// Postgres would report it.
+    #[derive(Debug)]
+    struct SqlState(&'static str);
+
+    impl std::fmt::Display for SqlState {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "SQLSTATE {}", self.0)
+        }
+    }
+
+    impl std::error::Error for SqlState {}
+
+    impl DatabaseError for SqlState {
+        fn message(&self) -> &str {
+            "error"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed(self.0))
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    fn db_err(code: &'static str) -> DbErr {
+        DbErr::Exec(RuntimeErr::SqlxError(sqlx::Error::Database(Box::new(
+            SqlState(code),
+        ))))
+    }
+
+    #[test]
+    fn sqlstates_are_classified() {
+        for (code, kind) in [
+            ("23505", ViolationKind::Unique),
+            ("23503", ViolationKind::ForeignKey),
+            ("23502", ViolationKind::NotNull),
+            ("23514", ViolationKind::Check),
+            ("40001", ViolationKind::SerializationFailure),
+            ("40P01", ViolationKind::SerializationFailure),
+        ] {
+            assert_eq!(ViolationKind::from_sqlstate(code), Some(kind), "{code}");
+        }
+        assert_eq!(ViolationKind::from_sqlstate("42P01"), None);
+    }
+
+    #[test]
+    fn database_errors_become_violations() {
+        let violation = ConstraintViolation::from_db_err(&db_err("23505")).unwrap();
+        assert_eq!(violation.kind, ViolationKind::Unique);
+        assert_eq!(violation.field, None);
+        assert_eq!(violation.status(), StatusCode::CONFLICT);
+
+        let violation = ConstraintViolation::from_db_err(&db_err("23502")).unwrap();
+        assert_eq!(violation.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn other_errors_are_not_violations() {
+        assert!(ConstraintViolation::from_db_err(&db_err("42P01")).is_none());
+        assert!(ConstraintViolation::from_db_err(&DbErr::RecordNotFound("x".into())).is_none());
+        assert!(
+            ConstraintViolation::from_db_err(&DbErr::Exec(RuntimeErr::Internal("x".into())))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn the_field_comes_from_the_column_constraint_or_detail() {
+        assert_eq!(
+            violated_field(Some("name"), Some("idx_users_email_lower"), None).as_deref(),
+            Some("name")
+        );
+        assert_eq!(
+            violated_field(None, Some("idx_users_email_lower"), None).as_deref(),
+            Some("email")
+        );
+        assert_eq!(
+            violated_field(
+                None,
+                Some("users_name_key"),
+                Some("Key (name)=(Jane) already exists.")
+            )
+            .as_deref(),
+            Some("name")
+        );
+        assert_eq!(
+            violated_field(None, None, Some("Key (lower(email))=(x) already exists.")),
+            None
+        );
+        assert_eq!(violated_field(None, None, None), None);
+    }
+}
@@ -0,0 +1,114 @@
+//! Conditional request support (RFC 9110 section 13) for user resources.
+
+use axum::http::{header, HeaderMap, HeaderValue};
+
+use crate::{entities::user, error::AppError};
+
+/// Strong ETag for a user, derived from its `version` column.
+pub fn for_user(user: &user::Model) -> String {
+    format!("\"{}\"", user.version)
+}
+
+pub fn header_value(etag: &str) -> HeaderValue {
+    HeaderValue::from_str(etag).expect("ETags are built from ASCII digits")
+}
+
+/// Splits an `If-Match` / `If-None-Match` value into its entity tags.
+fn tags(headers: &HeaderMap, name: header::HeaderName) -> Option<Vec<String>> {
+    let values: Vec<String> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+
+    (!values.is_empty()).then_some(values)
+}
+
+/// Enforces `If-Match` using strong comparison; weak tags never match.
+///
+/// Requests without the header are allowed through unconditionally.
+pub fn check_if_match(headers: &HeaderMap, current: &str) -> Result<(), AppError> {
+    let Some(tags) = tags(headers, header::IF_MATCH) else {
+        return Ok(());
+    };
+
+    if tags.iter().any(|tag| tag == "*" || tag == current) {
+        Ok(())
+    } else {
+        Err(AppError::PreconditionFailed(
+            "the user was modified since it was last fetched".to_string(),
+        ))
+    }
+}
+
+/// Whether `If-None-Match` matches the current ETag (weak comparison),
+/// meaning a GET can be answered with `304 Not Modified`.
+pub fn if_none_match(headers: &HeaderMap, current: &str) -> bool {
+    let Some(tags) = tags(headers, header::IF_NONE_MATCH) else {
+        return false;
+    };
+
+    tags.iter()
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURRENT: &str = "\"3\"";
+
+    fn headers(name: header::HeaderName, values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(name.clone(), HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn if_match_is_optional() {
+        assert!(check_if_match(&HeaderMap::new(), CURRENT).is_ok());
+    }
+
+    #[test]
+    fn if_match_accepts_the_current_tag_or_a_wildcard() {
+        for value in ["\"3\"", "*", "\"1\", \"3\""] {
+            let headers = headers(header::IF_MATCH, &[value]);
+            assert!(check_if_match(&headers, CURRENT).is_ok(), "{value}");
+        }
+        let split = headers(header::IF_MATCH, &["\"1\"", "\"3\""]);
+        assert!(check_if_match(&split, CURRENT).is_ok());
+    }
+
+    #[test]
+    fn if_match_rejects_stale_and_weak_tags() {
+        for value in ["\"2\"", "W/\"3\""] {
+            let headers = headers(header::IF_MATCH, &[value]);
+            assert!(matches!(
+                check_if_match(&headers, CURRENT),
+                Err(AppError::PreconditionFailed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn if_none_match_uses_weak_comparison() {
+        for value in ["\"3\"", "W/\"3\"", "*", "\"1\", W/\"3\""] {
+            let headers = headers(header::IF_NONE_MATCH, &[value]);
+            assert!(if_none_match(&headers, CURRENT), "{value}");
+        }
+    }
+
+    #[test]
+    fn if_none_match_misses_other_tags() {
+        assert!(!if_none_match(&HeaderMap::new(), CURRENT));
+        assert!(!if_none_match(
+            &headers(header::IF_NONE_MATCH, &["\"2\""]),
+            CURRENT
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+//! Wrappers around Axum's extractors that reject with [`AppError`], so
+//! malformed bodies, queries and paths get problem+json responses too.
+
+use axum::{
+    extract::{FromRequest, FromRequestParts, OptionalFromRequest, Request},
+    http::header,
+};
+use serde::de::DeserializeOwned;
+use validator::Validate;
+
+use crate::error::AppError;
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct AppJson<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct AppQuery<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct AppPath<T>(pub T);
+
+/// JSON body that is validated before the handler runs; failures become a
+/// `422` listing every invalid field.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let AppJson(value) = AppJson::<T>::from_request(req, state).await?;
+        value.validate()?;
+        Ok(Self(value))
+    }
+}
+
+/// Without a `Content-Type` the body counts as absent, so `Option<ValidatedJson<T>>`
+/// is `None`; a body that is sent must still be valid.
+impl<T, S> OptionalFromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Option<Self>, Self::Rejection> {
+        if !req.headers().contains_key(header::CONTENT_TYPE) {
+            return Ok(None);
+        }
+        <Self as FromRequest<S>>::from_request(req, state)
+            .await
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::StatusCode,
+        routing::post,
+        Router,
+    };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::handlers::CreateUserRequest;
+
+    async fn create(content_type: Option<&str>, body: &str) -> (StatusCode, Option<String>, Value) {
+        let app = Router::new().route(
+            "/users",
+            post(|ValidatedJson(_): ValidatedJson<CreateUserRequest>| async {
+                StatusCode::CREATED
+            }),
+        );
+        let mut request = Request::post("/users");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        let request = request.body(Body::from(body.to_string())).unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        (status, content_type, body)
+    }
+
+    #[tokio::test]
+    async fn invalid_fields_are_listed_in_a_problem() {
+        let body = r#"{"name":"","email":"not-an-email","password":"short"}"#;
+
+        let (status, content_type, problem) = create(Some("application/json"), body).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(content_type.as_deref(), Some("application/problem+json"));
+        assert_eq!(
+            problem,
+            json!({
+                "type": "about:blank",
+                "title": "Unprocessable Entity",
+                "status": 422,
+                "code": "validation_failed",
+                "detail": "request body failed validation",
+                "errors": [
+                    {"field": "email", "code": "email", "message": "must be a valid email address"},
+                    {"field": "name", "code": "length", "message": "must be 1 to 255 characters"},
+                    {"field": "password", "code": "length", "message": "must be 8 to 128 characters"},
+                ],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn valid_bodies_reach_the_handler() {
+        let body = r#"{"name":"Jane","email":"jane@example.com","password":"a long password"}"#;
+
+        let (status, _, _) = create(Some("application/json"), body).await;
+
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[tokio::test]
+    async fn malformed_bodies_are_problems_too() {
+        let (status, content_type, problem) = create(Some("application/json"), "{").await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(content_type.as_deref(), Some("application/problem+json"));
+        assert_eq!(problem["code"], "invalid_body");
+        assert!(problem.get("errors").is_none());
+    }
+
+    #[tokio::test]
+    async fn bodies_must_be_json() {
+        let (status, _, problem) = create(None, "{}").await;
+
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(problem["status"], 415);
+    }
+}
@@ -1,27 +1,106 @@
+use std::collections::BTreeMap;
+
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    body::Bytes,
+    extract::{OriginalUri, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{AppendHeaders, IntoResponse, Response},
     Json,
 };
+use chrono::{DateTime, Utc};
 use sea_orm::{
-    ActiveModelTrait, EntityTrait, Set,
+    sea_query::{Expr, Func, LikeExpr, SimpleExpr},
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseTransaction, DbErr, EntityTrait, Order,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Select, Set,
 };
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-use crate::{entities::user, AppState};
+use crate::{
+    auth::{
+        api_key::{self, Scope},
+        password_reset,
+        policy::UserAccess,
+        refresh, session,
+        totp::{self, Enrollment},
+        verify_email, AllowUnenrolled, Principal, RequireAdmin,
+    },
+    db::Traced,
+    entities::{
+        api_key as api_key_entity,
+        user::{self, Role},
+    },
+    error::AppError,
+    etag,
+    extract::{AppPath, AppQuery, ValidatedJson},
+    migration,
+    pagination::{self, Cursor, Direction},
+    password::Verified,
+    validation::{self, MAX_PASSWORD_LENGTH, MAX_TEXT_LENGTH, MIN_PASSWORD_LENGTH},
+    AppState,
+};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Validate)]
 pub struct CreateUserRequest {
+    #[serde(deserialize_with = "validation::trimmed")]
+    #[validate(
+        length(
+            min = 1,
+            max = "MAX_TEXT_LENGTH",
+            message = "must be 1 to 255 characters"
+        ),
+        custom(function = "validation::no_control_chars")
+    )]
     pub name: String,
+    #[serde(deserialize_with = "validation::trimmed")]
+    #[validate(
+        length(max = "MAX_TEXT_LENGTH", message = "must be at most 255 characters"),
+        email(message = "must be a valid email address")
+    )]
     pub email: String,
+    #[serde(skip_serializing)]
+    #[validate(length(
+        min = "MIN_PASSWORD_LENGTH",
+        max = "MAX_PASSWORD_LENGTH",
+        message = "must be 8 to 128 characters"
+    ))]
+    pub password: String,
 }
 
-#[derive(Deserialize, Serialize)]
+/// Full replacement of a user's editable fields, used by PUT and as the
+/// target document for PATCH.
+#[derive(Deserialize, Serialize, Validate)]
 pub struct UpdateUserRequest {
-    pub name: Option<String>,
-    pub email: Option<String>,
+    #[serde(deserialize_with = "validation::trimmed")]
+    #[validate(
+        length(
+            min = 1,
+            max = "MAX_TEXT_LENGTH",
+            message = "must be 1 to 255 characters"
+        ),
+        custom(function = "validation::no_control_chars")
+    )]
+    pub name: String,
+    #[serde(deserialize_with = "validation::trimmed")]
+    #[validate(
+        length(max = "MAX_TEXT_LENGTH", message = "must be at most 255 characters"),
+        email(message = "must be a valid email address")
+    )]
+    pub email: String,
+}
+
+impl From<&user::Model> for UpdateUserRequest {
+    fn from(model: &user::Model) -> Self {
+        Self {
+            name: model.name.clone(),
+            email: model.email.clone(),
+        }
+    }
 }
 
+pub const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+pub const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+
 #[derive(Serialize)]
 pub struct UserResponse {
     pub id: i32,
@@ -29,6 +108,10 @@ pub struct UserResponse {
     pub email: String,
     pub created_at: String,
     pub updated_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+    pub role: Role,
+    pub email_verified_at: Option<String>,
 }
 
 impl From<user::Model> for UserResponse {
@@ -39,122 +122,2010 @@ impl From<user::Model> for UserResponse {
             email: model.email,
             created_at: model.created_at.to_string(),
             updated_at: model.updated_at.to_string(),
+            deleted_at: model.deleted_at.map(|t| t.to_string()),
+            role: model.role,
+            email_verified_at: model.email_verified_at.map(|t| t.to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ChangePasswordRequest {
+    /// Required unless an admin is changing someone else's password.
+    pub current_password: Option<String>,
+    #[validate(length(
+        min = "MIN_PASSWORD_LENGTH",
+        max = "MAX_PASSWORD_LENGTH",
+        message = "must be 8 to 128 characters"
+    ))]
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ChangeRoleRequest {
+    pub role: Role,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct LoginRequest {
+    #[serde(deserialize_with = "validation::trimmed")]
+    #[validate(length(max = "MAX_TEXT_LENGTH", message = "must be at most 255 characters"))]
+    pub email: String,
+    #[validate(length(
+        max = "MAX_PASSWORD_LENGTH",
+        message = "must be at most 128 characters"
+    ))]
+    pub password: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct PasswordResetRequest {
+    #[serde(deserialize_with = "validation::trimmed")]
+    #[validate(length(max = "MAX_TEXT_LENGTH", message = "must be at most 255 characters"))]
+    pub email: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ConfirmPasswordResetRequest {
+    #[validate(length(max = 256, message = "must be at most 256 characters"))]
+    pub token: String,
+    #[validate(length(
+        min = "MIN_PASSWORD_LENGTH",
+        max = "MAX_PASSWORD_LENGTH",
+        message = "must be 8 to 128 characters"
+    ))]
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct VerifyEmailRequest {
+    #[validate(length(max = 1024, message = "must be at most 1024 characters"))]
+    pub token: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct RefreshRequest {
+    #[validate(length(max = 256, message = "must be at most 256 characters"))]
+    pub refresh_token: String,
+}
+
+/// Issued on sign-in and refresh, shaped like an OAuth 2.0 token response.
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: &'static str,
+    /// Seconds until `access_token` expires.
+    pub expires_in: u64,
+    pub refresh_token: String,
+}
+
+/// Returned when a cookie session starts. `csrf_token` is also set as a
+/// cookie; either way it must be sent back in `X-CSRF-Token`.
+#[derive(Serialize)]
+pub struct NewSessionResponse {
+    pub id: i64,
+    pub csrf_token: String,
+    pub expires_at: String,
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub id: i64,
+    /// Whether this is the session the request was made with.
+    pub current: bool,
+    pub user_agent: Option<String>,
+    pub created_at: String,
+    pub last_seen_at: String,
+    pub expires_at: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+    #[serde(deserialize_with = "validation::trimmed")]
+    #[validate(
+        length(
+            min = 1,
+            max = "MAX_TEXT_LENGTH",
+            message = "must be 1 to 255 characters"
+        ),
+        custom(function = "validation::no_control_chars")
+    )]
+    pub name: String,
+    #[validate(length(min = 1, message = "must grant at least one scope"))]
+    pub scopes: Vec<Scope>,
+    /// Never expires if omitted.
+    #[validate(custom(function = "validation::in_future"))]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct ApiKeyResponse {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub last_used_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<String>,
+}
+
+impl From<api_key_entity::Model> for ApiKeyResponse {
+    fn from(model: api_key_entity::Model) -> Self {
+        Self {
+            id: model.id,
+            name: model.name,
+            prefix: model.prefix,
+            scopes: model.scopes,
+            created_at: model.created_at.to_string(),
+            expires_at: model.expires_at.map(|t| t.to_string()),
+            last_used_at: model.last_used_at.map(|t| t.to_string()),
+            revoked_at: model.revoked_at.map(|t| t.to_string()),
+        }
+    }
+}
+
+/// Returned once, on creation; the key can't be retrieved later.
+#[derive(Serialize)]
+pub struct CreatedApiKeyResponse {
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+    pub key: String,
+}
+
+#[derive(Deserialize, Validate)]
+pub struct TotpCodeRequest {
+    #[validate(length(max = 64, message = "must be at most 64 characters"))]
+    pub code: String,
+}
+
+/// Second step of a sign-in: the challenge from the first and a TOTP or
+/// recovery code.
+#[derive(Deserialize, Validate)]
+pub struct MfaLoginRequest {
+    #[validate(length(max = 1024, message = "must be at most 1024 characters"))]
+    pub mfa_token: String,
+    #[validate(length(max = 64, message = "must be at most 64 characters"))]
+    pub code: String,
+}
+
+/// Returned in place of tokens or a session when the password was right
+/// but the user has two-factor authentication enabled.
+#[derive(Serialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub mfa_token: String,
+    pub expires_in: u64,
+}
+
+#[derive(Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct MfaRequiredRolesResponse {
+    pub roles: Vec<Role>,
+}
+
+#[derive(Deserialize)]
+pub struct GetUserQuery {
+    /// Admin-only: also return soft-deleted users.
+    pub include_deleted: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct ListUsersQuery {
+    pub page: Option<u64>,
+    pub limit: Option<u64>,
+    pub name: Option<String>,
+    pub name_contains: Option<String>,
+    pub email: Option<String>,
+    pub email_contains: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    pub sort: Option<String>,
+    /// `cursor` switches to keyset pagination; without a cursor yet,
+    /// `pagination=cursor` requests the first page in that mode.
+    pub cursor: Option<String>,
+    pub pagination: Option<String>,
+    /// Admin-only: also list soft-deleted users.
+    pub include_deleted: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct UserListResponse {
+    pub data: Vec<UserResponse>,
+    pub page: u64,
+    pub limit: u64,
+    pub total: u64,
+    pub total_pages: u64,
+}
+
+#[derive(Serialize)]
+pub struct UserCursorResponse {
+    pub data: Vec<UserResponse>,
+    pub limit: u64,
+    pub next_cursor: Option<String>,
+    pub prev_cursor: Option<String>,
+}
+
+pub const DEFAULT_PAGE_LIMIT: u64 = 20;
+pub const MAX_PAGE_LIMIT: u64 = 100;
+
+impl ListUsersQuery {
+    fn limit(&self) -> u64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_LIMIT)
+            .clamp(1, MAX_PAGE_LIMIT)
+    }
+
+    fn is_cursor_mode(&self) -> bool {
+        self.cursor.is_some() || self.pagination.as_deref() == Some("cursor")
+    }
+
+    /// Applies the name, email and timestamp filters to a user query.
+    fn apply_filters(&self, mut select: Select<user::Entity>) -> Select<user::Entity> {
+        if self.include_deleted != Some(true) {
+            select = select.filter(user::Column::DeletedAt.is_null());
+        }
+        if let Some(name) = &self.name {
+            select = select.filter(user::Column::Name.eq(name.as_str()));
+        }
+        if let Some(name) = &self.name_contains {
+            select = select.filter(user::Column::Name.like(contains(name)));
+        }
+        // Emails are unique case-insensitively, so match them the same way.
+        if let Some(email) = &self.email {
+            select = select.filter(lower_email().eq(email.trim().to_lowercase()));
+        }
+        if let Some(email) = &self.email_contains {
+            select = select.filter(lower_email().like(contains(&email.to_lowercase())));
+        }
+        if let Some(after) = self.created_after {
+            select = select.filter(user::Column::CreatedAt.gte(after.naive_utc()));
         }
+        if let Some(before) = self.created_before {
+            select = select.filter(user::Column::CreatedAt.lt(before.naive_utc()));
+        }
+        if let Some(after) = self.updated_after {
+            select = select.filter(user::Column::UpdatedAt.gte(after.naive_utc()));
+        }
+        if let Some(before) = self.updated_before {
+            select = select.filter(user::Column::UpdatedAt.lt(before.naive_utc()));
+        }
+        select
+    }
+
+    /// Parses `sort=-created_at,name` into column/direction pairs.
+    /// A leading `-` sorts descending; unknown fields are rejected.
+    fn sort_order(&self) -> Result<Vec<(user::Column, Order)>, AppError> {
+        let Some(sort) = &self.sort else {
+            return Ok(Vec::new());
+        };
+
+        sort.split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(|field| {
+                let (field, order) = match field.strip_prefix('-') {
+                    Some(field) => (field, Order::Desc),
+                    None => (field, Order::Asc),
+                };
+                let column = match field {
+                    "id" => user::Column::Id,
+                    "name" => user::Column::Name,
+                    "email" => user::Column::Email,
+                    "created_at" => user::Column::CreatedAt,
+                    "updated_at" => user::Column::UpdatedAt,
+                    _ => {
+                        return Err(AppError::BadRequest(format!(
+                            "unknown sort field `{field}`"
+                        )))
+                    }
+                };
+                Ok((column, order))
+            })
+            .collect()
+    }
+}
+
+/// A `LIKE` pattern matching strings that contain `value`, with `%`, `_`
+/// and `\` in it taken literally rather than as wildcards.
+fn contains(value: &str) -> LikeExpr {
+    LikeExpr::new(format!("%{}%", escape_like(value))).escape('\\')
+}
+
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
     }
+    escaped
+}
+
+/// `lower(users.email)`, matching the `idx_users_email_lower` unique index.
+fn lower_email() -> SimpleExpr {
+    Func::lower(Expr::col((user::Entity, user::Column::Email))).into()
 }
 
 #[derive(Serialize)]
 pub struct HealthCheckResponse {
     pub status: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(flatten)]
+    pub details: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Self {
+            status: "ok",
+            error: None,
+            details: serde_json::Map::new(),
+        }
+    }
+
+    fn fail(error: impl ToString) -> Self {
+        Self {
+            status: "fail",
+            error: Some(error.to_string()),
+            details: serde_json::Map::new(),
+        }
+    }
+
+    /// A failure caused by `err`. The error is logged rather than shown,
+    /// since it can name hosts, roles and schema details.
+    fn database_error(check: &str, err: DbErr) -> Self {
+        tracing::warn!(check, error = %err, "readiness check failed");
+        Self::fail("database error")
+    }
+
+    /// Adds the fields of a JSON object to the check's output.
+    fn with(mut self, details: serde_json::Value) -> Self {
+        if let serde_json::Value::Object(map) = details {
+            self.details.extend(map);
+        }
+        self
+    }
+
+    fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
 }
 
-pub async fn health_check() -> Json<HealthCheckResponse> {
+/// Liveness: the process is up and serving requests. Never touches the
+/// database, so a Postgres outage does not get the app restarted.
+pub async fn liveness() -> Json<HealthCheckResponse> {
     Json(HealthCheckResponse {
         status: "ok".to_string(),
+        checks: BTreeMap::new(),
     })
 }
 
+/// Readiness: the app can do useful work. Fails with `503` when the database
+/// is unreachable, migrations are pending, or shutdown has started.
+pub async fn readiness(State(state): State<AppState>) -> (StatusCode, Json<HealthCheckResponse>) {
+    let timeout = state.health_check_timeout;
+    let mut checks = BTreeMap::new();
+
+    let started = std::time::Instant::now();
+    let database = match tokio::time::timeout(timeout, state.db.ping()).await {
+        Ok(Ok(())) => Check::ok()
+            .with(serde_json::json!({ "latency_ms": started.elapsed().as_millis() as u64 })),
+        Ok(Err(e)) => Check::database_error("database", e),
+        Err(_) => Check::fail(format!("no response within {timeout:?}")),
+    };
+    checks.insert("database", database);
+
+    let pool = state.db.get_postgres_connection_pool();
+    checks.insert(
+        "pool",
+        Check::ok().with(serde_json::json!({
+            "size": pool.size(),
+            "idle": pool.num_idle(),
+            "max": pool.options().get_max_connections(),
+        })),
+    );
+
+    let migrations = match tokio::time::timeout(timeout, migration::pending(&state.db)).await {
+        Ok(Ok(pending)) if pending.is_empty() => {
+            Check::ok().with(serde_json::json!({ "pending": pending }))
+        }
+        Ok(Ok(pending)) => {
+            Check::fail("schema is behind").with(serde_json::json!({ "pending": pending }))
+        }
+        Ok(Err(e)) => Check::database_error("migrations", e),
+        Err(_) => Check::fail(format!("no response within {timeout:?}")),
+    };
+    checks.insert("migrations", migrations);
+
+    checks.insert(
+        "shutdown",
+        if state.shutdown.is_set() {
+            Check::fail("shutting down")
+        } else {
+            Check::ok()
+        },
+    );
+
+    let (code, status) = if checks.values().all(Check::is_ok) {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+    let body = HealthCheckResponse {
+        status: status.to_string(),
+        checks,
+    };
+    (code, Json(body))
+}
+
 pub async fn create_user(
     State(state): State<AppState>,
-    Json(payload): Json<CreateUserRequest>,
-) -> Result<(StatusCode, Json<UserResponse>), StatusCode> {
+    ValidatedJson(payload): ValidatedJson<CreateUserRequest>,
+) -> Result<(StatusCode, Json<UserResponse>), AppError> {
     let now = chrono::Utc::now().naive_utc();
+    let password_hash = state.passwords.hash(payload.password).await?;
 
     let user = user::ActiveModel {
         name: Set(payload.name),
         email: Set(payload.email),
         created_at: Set(now),
         updated_at: Set(now),
+        password_hash: Set(Some(password_hash)),
         ..Default::default()
     };
 
-    let user = user
-        .insert(&state.db)
-        .await
-        .map_err(|e| {
-            if e.to_string().contains("duplicate key") || e.to_string().contains("unique constraint") {
-                StatusCode::CONFLICT
-            } else {
-                StatusCode::INTERNAL_SERVER_ERROR
-            }
-        })?;
+    let user = user.insert(&state.db).await?;
+
+    verify_email::send(&state, &user).await;
 
     Ok((StatusCode::CREATED, Json(user.into())))
 }
 
 pub async fn list_users(
     State(state): State<AppState>,
-) -> Result<Json<Vec<UserResponse>>, StatusCode> {
-    let users = user::Entity::find()
-        .all(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    principal: Principal,
+    OriginalUri(uri): OriginalUri,
+    AppQuery(params): AppQuery<ListUsersQuery>,
+) -> Result<Response, AppError> {
+    // Members may only see themselves, via `GET /users/{id}`
+    principal.require_admin()?;
+
+    if params.is_cursor_mode() {
+        return list_users_by_cursor(&state, &uri, &params).await;
+    }
+
+    let page = params.page.unwrap_or(1).max(1);
+    let limit = params.limit();
+
+    let mut select = params.apply_filters(user::Entity::find());
+    for (column, order) in params.sort_order()? {
+        select = select.order_by(column, order);
+    }
+    // Always finish with the primary key so pages are stable.
+    select = select.order_by_asc(user::Column::Id);
+
+    let paginator = select.paginate(&state.db, limit);
+    let counts = paginator.num_items_and_pages().await?;
+    let users = paginator.fetch_page(page - 1).await?;
 
     let users: Vec<UserResponse> = users.into_iter().map(|u| u.into()).collect();
 
-    Ok(Json(users))
+    Ok(Json(UserListResponse {
+        data: users,
+        page,
+        limit,
+        total: counts.number_of_items,
+        total_pages: counts.number_of_pages,
+    })
+    .into_response())
+}
+
+/// Keyset pagination over `(created_at, id)`, which stays consistent
+/// under concurrent inserts and doesn't degrade with the page number.
+async fn list_users_by_cursor(
+    state: &AppState,
+    uri: &axum::http::Uri,
+    params: &ListUsersQuery,
+) -> Result<Response, AppError> {
+    // The keyset fixes the ordering, so custom sorts and page numbers don't apply.
+    if params.sort.is_some() || params.page.is_some() {
+        return Err(AppError::BadRequest(
+            "`sort` and `page` cannot be combined with cursor pagination".to_string(),
+        ));
+    }
+
+    let limit = params.limit();
+    let position = params
+        .cursor
+        .as_deref()
+        .map(|c| {
+            Cursor::decode(c).ok_or_else(|| AppError::BadRequest("invalid cursor".to_string()))
+        })
+        .transpose()?;
+
+    let mut cursor = params
+        .apply_filters(user::Entity::find())
+        .cursor_by((user::Column::CreatedAt, user::Column::Id));
+
+    // Fetch one extra row to find out whether another page exists.
+    let direction = position.as_ref().map_or(Direction::Next, |p| p.direction);
+    match &position {
+        Some(p) if p.direction == Direction::Prev => {
+            cursor.before((p.created_at, p.id)).last(limit + 1);
+        }
+        Some(p) => {
+            cursor.after((p.created_at, p.id)).first(limit + 1);
+        }
+        None => {
+            cursor.first(limit + 1);
+        }
+    }
+
+    let mut users = cursor.all(&state.db).await?;
+
+    let has_more = users.len() as u64 > limit;
+    if has_more {
+        match direction {
+            Direction::Next => {
+                users.pop();
+            }
+            Direction::Prev => {
+                users.remove(0);
+            }
+        }
+    }
+
+    let cursor_at = |user: &user::Model, direction| {
+        Cursor {
+            direction,
+            created_at: user.created_at,
+            id: user.id,
+        }
+        .encode()
+    };
+
+    let (has_next, has_prev) = match direction {
+        Direction::Next => (has_more, position.is_some()),
+        Direction::Prev => (true, has_more),
+    };
+    let next_cursor = users
+        .last()
+        .filter(|_| has_next)
+        .map(|u| cursor_at(u, Direction::Next));
+    let prev_cursor = users
+        .first()
+        .filter(|_| has_prev)
+        .map(|u| cursor_at(u, Direction::Prev));
+
+    let mut links = Vec::new();
+    if let Some(next) = &next_cursor {
+        links.push(("next", next.as_str()));
+    }
+    if let Some(prev) = &prev_cursor {
+        links.push(("prev", prev.as_str()));
+    }
+    let link = pagination::link_header(uri.path(), uri.query(), &links);
+
+    let body = Json(UserCursorResponse {
+        data: users.into_iter().map(|u| u.into()).collect(),
+        limit,
+        next_cursor,
+        prev_cursor,
+    });
+
+    Ok(match link {
+        Some(link) => ([(header::LINK, link)], body).into_response(),
+        None => body.into_response(),
+    })
 }
 
 pub async fn get_user(
     State(state): State<AppState>,
-    Path(id): Path<i32>,
-) -> Result<Json<UserResponse>, StatusCode> {
-    let user = user::Entity::find_by_id(id)
+    UserAccess { principal, id }: UserAccess,
+    AppQuery(params): AppQuery<GetUserQuery>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let mut select = user::Entity::find_by_id(id);
+    if params.include_deleted == Some(true) {
+        principal.require_admin()?;
+    } else {
+        select = select.filter(user::Column::DeletedAt.is_null());
+    }
+
+    let user = select
         .one(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .await?
+        .ok_or_else(|| AppError::user_not_found(id))?;
+
+    let etag = etag::for_user(&user);
+    if etag::if_none_match(&headers, &etag) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag::header_value(&etag))],
+        )
+            .into_response());
+    }
 
-    Ok(Json(user.into()))
+    Ok(user_response(user))
 }
 
 pub async fn update_user(
     State(state): State<AppState>,
-    Path(id): Path<i32>,
-    Json(payload): Json<UpdateUserRequest>,
-) -> Result<Json<UserResponse>, StatusCode> {
+    UserAccess { id, .. }: UserAccess,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<UpdateUserRequest>,
+) -> Result<Response, AppError> {
+    let txn = state.db.begin().await?;
+
+    let user = find_user_for_update(&txn, id, &headers).await?;
+    let (user, email_changed) = replace_user(&txn, user, payload).await?;
+
+    txn.commit().await?;
+
+    if email_changed {
+        verify_email::send(&state, &user).await;
+    }
+
+    Ok(user_response(user))
+}
+
+/// A `PATCH /users/{id}` body, by content type.
+enum UserPatch {
+    Merge(serde_json::Value),
+    Json(json_patch::Patch),
+}
+
+/// Partial update via JSON Merge Patch (RFC 7396) or JSON Patch (RFC 6902).
+///
+/// Patches apply to the editable document `{"name": ..., "email": ...}`;
+/// the result must be a valid [`UpdateUserRequest`].
+pub async fn patch_user(
+    State(state): State<AppState>,
+    UserAccess { id, .. }: UserAccess,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+
+    // Parsed up front, so malformed requests never lock the row
+    let patch = match content_type.as_str() {
+        MERGE_PATCH_CONTENT_TYPE => {
+            UserPatch::Merge(serde_json::from_slice(&body).map_err(AppError::invalid_body)?)
+        }
+        JSON_PATCH_CONTENT_TYPE => {
+            UserPatch::Json(serde_json::from_slice(&body).map_err(AppError::invalid_body)?)
+        }
+        _ => {
+            return Err(AppError::UnsupportedMediaType(format!(
+                "expected {MERGE_PATCH_CONTENT_TYPE} or {JSON_PATCH_CONTENT_TYPE}"
+            )))
+        }
+    };
+
+    let txn = state.db.begin().await?;
+
+    let user = find_user_for_update(&txn, id, &headers).await?;
+
+    let mut document = serde_json::json!(UpdateUserRequest::from(&user));
+
+    match patch {
+        UserPatch::Merge(patch) => json_patch::merge(&mut document, &patch),
+        UserPatch::Json(patch) => json_patch::patch(&mut document, &patch)?,
+    }
+
+    if let Some(field) = document.as_object().and_then(|fields| {
+        fields
+            .keys()
+            .find(|k| !["name", "email"].contains(&k.as_str()))
+    }) {
+        return Err(AppError::invalid_patch(format!("unknown field `{field}`")));
+    }
+
+    let payload: UpdateUserRequest =
+        serde_json::from_value(document).map_err(|e| AppError::invalid_patch(e.to_string()))?;
+    payload.validate()?;
+
+    let (user, email_changed) = replace_user(&txn, user, payload).await?;
+
+    txn.commit().await?;
+
+    if email_changed {
+        verify_email::send(&state, &user).await;
+    }
+
+    Ok(user_response(user))
+}
+
+/// Loads a live user with `SELECT ... FOR UPDATE` and checks `If-Match` against
+/// it, so the precondition holds until the surrounding transaction commits.
+async fn find_user_for_update(
+    txn: &Traced<DatabaseTransaction>,
+    id: i32,
+    headers: &HeaderMap,
+) -> Result<user::Model, AppError> {
     let user = user::Entity::find_by_id(id)
-        .one(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .filter(user::Column::DeletedAt.is_null())
+        .lock_exclusive()
+        .one(txn)
+        .await?
+        .ok_or_else(|| AppError::user_not_found(id))?;
+
+    etag::check_if_match(headers, &etag::for_user(&user))?;
 
+    Ok(user)
+}
+
+/// Applies `payload` to `user`. A new email address starts out unverified;
+/// the returned flag tells whether it changed.
+async fn replace_user<C: ConnectionTrait>(
+    db: &C,
+    user: user::Model,
+    payload: UpdateUserRequest,
+) -> Result<(user::Model, bool), AppError> {
+    // Compared the way the unique index does, so changing only the case
+    // keeps the address verified; `before_save` trims the stored address
+    let email_changed = payload.email.trim().to_lowercase() != user.email.to_lowercase();
     let mut user: user::ActiveModel = user.into();
 
-    if let Some(name) = payload.name {
-        user.name = Set(name);
+    user.name = Set(payload.name);
+    user.email = Set(payload.email);
+    if email_changed {
+        user.email_verified_at = Set(None);
+        user.email_verification_nonce = Set(None);
+    }
+    user.updated_at = Set(chrono::Utc::now().naive_utc());
+    let user = user.update(db).await?;
+
+    // Reset links went to the old address
+    if email_changed {
+        password_reset::invalidate(db, user.id).await?;
     }
 
-    if let Some(email) = payload.email {
-        user.email = Set(email);
+    Ok((user, email_changed))
+}
+
+/// Renders a single user along with its `ETag`.
+fn user_response(user: user::Model) -> Response {
+    let etag = etag::header_value(&etag::for_user(&user));
+    ([(header::ETAG, etag)], Json(UserResponse::from(user))).into_response()
+}
+
+/// Sets a new password. Callers must confirm the current password, except
+/// admins setting someone else's, e.g. for accounts that never had one.
+pub async fn change_password(
+    State(state): State<AppState>,
+    UserAccess { principal, id }: UserAccess,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<ChangePasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    let txn = state.db.begin().await?;
+
+    let user = find_user_for_update(&txn, id, &headers).await?;
+
+    let by_admin = principal.is_admin() && principal.user_id != Some(id);
+    if !by_admin {
+        let current = payload
+            .current_password
+            .ok_or_else(|| AppError::BadRequest("current_password is required".to_string()))?;
+        let verified = state
+            .passwords
+            .verify(user.password_hash.clone(), current)
+            .await?;
+        if verified == Verified::Invalid {
+            return Err(AppError::Forbidden(
+                "current password is incorrect".to_string(),
+            ));
+        }
     }
 
+    let password_hash = state.passwords.hash(payload.new_password).await?;
+    let mut user: user::ActiveModel = user.into();
+    user.password_hash = Set(Some(password_hash));
     user.updated_at = Set(chrono::Utc::now().naive_utc());
+    user.update(&txn).await?;
 
-    let user = user
-        .update(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    txn.commit().await?;
 
-    Ok(Json(user.into()))
+    Ok(StatusCode::NO_CONTENT)
 }
 
-pub async fn delete_user(
+/// Confirms an email address with the token from a verification link.
+pub async fn verify_email(
+    State(state): State<AppState>,
+    ValidatedJson(payload): ValidatedJson<VerifyEmailRequest>,
+) -> Result<Response, AppError> {
+    let invalid =
+        || AppError::BadRequest("verification token is invalid, expired or already used".into());
+    let (id, claims) = verify_email::verify(&state, &payload.token).ok_or_else(invalid)?;
+
+    let txn = state.db.begin().await?;
+
+    let user = user::Entity::find_by_id(id)
+        .filter(user::Column::DeletedAt.is_null())
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+        .ok_or_else(invalid)?;
+
+    // Used already, superseded by a newer link, or sent to an address the
+    // user has since replaced
+    let current = user.email_verification_nonce.as_deref() == Some(claims.nonce.as_str())
+        && user.email.to_lowercase() == claims.email.to_lowercase();
+    if user.email_verified_at.is_some() || !current {
+        return Err(invalid());
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let mut user: user::ActiveModel = user.into();
+    user.email_verified_at = Set(Some(now));
+    user.email_verification_nonce = Set(None);
+    user.updated_at = Set(now);
+    let user = user.update(&txn).await?;
+
+    txn.commit().await?;
+
+    tracing::info!(user_id = user.id, "verified email address");
+
+    Ok(user_response(user))
+}
+
+/// Sends a new verification link to a user's current address, at most
+/// once per [`verify_email::RESEND_INTERVAL`].
+pub async fn resend_verification_email(
     State(state): State<AppState>,
-    Path(id): Path<i32>,
-) -> Result<StatusCode, StatusCode> {
+    UserAccess { id, .. }: UserAccess,
+) -> Result<StatusCode, AppError> {
     let user = user::Entity::find_by_id(id)
+        .filter(user::Column::DeletedAt.is_null())
         .one(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
+        .await?
+        .ok_or_else(|| AppError::user_not_found(id))?;
+
+    if user.email_verified_at.is_some() {
+        return Err(AppError::BadRequest(
+            "email address is already verified".to_string(),
+        ));
+    }
+    if !state.verification_throttle.allow(&id.to_string()) {
+        return Err(AppError::Rejection {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            code: "too_many_requests",
+            detail: "a verification email was sent recently; try again later".to_string(),
+        });
+    }
+
+    verify_email::send(&state, &user).await;
 
-    let user: user::ActiveModel = user.into();
+    Ok(StatusCode::ACCEPTED)
+}
 
-    user.delete(&state.db)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// Signs an access token for `user_id` and pairs it with `refresh_token`.
+fn token_response(
+    state: &AppState,
+    user_id: i32,
+    refresh_token: String,
+) -> Result<Json<TokenResponse>, AppError> {
+    let access_token = state
+        .jwt
+        .issue(user_id)
+        .map_err(|e| AppError::Internal(format!("failed to sign access token: {e}")))?;
 
-    Ok(StatusCode::NO_CONTENT)
+    Ok(Json(TokenResponse {
+        access_token,
+        token_type: "Bearer",
+        expires_in: state.jwt.access_ttl.as_secs(),
+        refresh_token,
+    }))
 }
 
+/// Makes a user an admin or a member. Admins can't change their own role,
+/// so there is always someone left to undo a demotion.
+pub async fn change_role(
+    State(state): State<AppState>,
+    principal: Principal,
+    AppPath(id): AppPath<i32>,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<ChangeRoleRequest>,
+) -> Result<Response, AppError> {
+    principal.require_role_change(id)?;
+
+    let txn = state.db.begin().await?;
+
+    let user = find_user_for_update(&txn, id, &headers).await?;
+    let previous = user.role;
+
+    let mut user: user::ActiveModel = user.into();
+    user.role = Set(payload.role);
+    user.updated_at = Set(chrono::Utc::now().naive_utc());
+    let user = user.update(&txn).await?;
+
+    txn.commit().await?;
+
+    tracing::info!(
+        user_id = id,
+        changed_by = principal.user_id,
+        from = ?previous,
+        to = ?user.role,
+        "changed user role"
+    );
+
+    Ok(user_response(user))
+}
+
+/// Checks an email and password, returning the user they belong to. Hashes
+/// made with outdated Argon2 settings are replaced with one using the
+/// current settings.
+async fn check_credentials(
+    state: &AppState,
+    payload: LoginRequest,
+) -> Result<user::Model, AppError> {
+    let user = user::Entity::find()
+        .filter(lower_email().eq(payload.email.to_lowercase()))
+        .filter(user::Column::DeletedAt.is_null())
+        .one(&state.db)
+        .await?;
+
+    let hash = user.as_ref().and_then(|u| u.password_hash.clone());
+    let verified = state
+        .passwords
+        .verify(hash, payload.password.clone())
+        .await?;
+
+    let (Some(user), Verified::Valid { rehash }) = (user, verified) else {
+        return Err(AppError::Unauthorized(
+            "invalid email or password".to_string(),
+        ));
+    };
+
+    if rehash {
+        let password_hash = state.passwords.hash(payload.password).await?;
+        // Not a user-visible change, so `version` and `updated_at` stay as is.
+        user::Entity::update_many()
+            .col_expr(user::Column::PasswordHash, Expr::value(password_hash))
+            .filter(user::Column::Id.eq(user.id))
+            .exec(&state.db)
+            .await?;
+        tracing::info!(
+            user_id = user.id,
+            "rehashed password with current parameters"
+        );
+    }
+
+    Ok(user)
+}
+
+/// The challenge to answer with a second factor, if `user_id` has
+/// two-factor authentication enabled.
+async fn mfa_challenge(
+    state: &AppState,
+    user_id: i32,
+) -> Result<Option<Json<MfaChallengeResponse>>, AppError> {
+    if !totp::is_enabled(&state.db, user_id).await? {
+        return Ok(None);
+    }
+
+    Ok(Some(Json(MfaChallengeResponse {
+        mfa_required: true,
+        mfa_token: totp::challenge(&state.jwt, user_id, state.totp.challenge_ttl)?,
+        expires_in: state.totp.challenge_ttl.as_secs(),
+    })))
+}
+
+/// Checks the second step of a sign-in, returning the user signing in.
+async fn check_second_factor(state: &AppState, payload: MfaLoginRequest) -> Result<i32, AppError> {
+    let user_id = totp::verify_challenge(&state.jwt, &payload.mfa_token).ok_or_else(|| {
+        AppError::Unauthorized("invalid or expired two-factor challenge".to_string())
+    })?;
+
+    totp::check(&state.db, user_id, &payload.code).await?;
+
+    Ok(user_id)
+}
+
+/// Signs in with an email and password, returning access and refresh tokens,
+/// or a challenge if the user has two-factor authentication enabled.
+pub async fn login(
+    State(state): State<AppState>,
+    ValidatedJson(payload): ValidatedJson<LoginRequest>,
+) -> Result<Response, AppError> {
+    let user = check_credentials(&state, payload).await?;
+
+    if let Some(challenge) = mfa_challenge(&state, user.id).await? {
+        return Ok(challenge.into_response());
+    }
+
+    let refresh_token = refresh::issue(&state.db, user.id, None, state.jwt.refresh_ttl).await?;
+
+    Ok(token_response(&state, user.id, refresh_token)?.into_response())
+}
+
+/// Completes a sign-in with a second factor, returning access and refresh
+/// tokens.
+pub async fn login_mfa(
+    State(state): State<AppState>,
+    ValidatedJson(payload): ValidatedJson<MfaLoginRequest>,
+) -> Result<Json<TokenResponse>, AppError> {
+    let user_id = check_second_factor(&state, payload).await?;
+
+    let refresh_token = refresh::issue(&state.db, user_id, None, state.jwt.refresh_ttl).await?;
+
+    token_response(&state, user_id, refresh_token)
+}
+
+/// Exchanges a refresh token for a new access and refresh token.
+pub async fn refresh(
+    State(state): State<AppState>,
+    ValidatedJson(payload): ValidatedJson<RefreshRequest>,
+) -> Result<Json<TokenResponse>, AppError> {
+    let (user_id, refresh_token) =
+        refresh::rotate(&state.db, &payload.refresh_token, state.jwt.refresh_ttl).await?;
+
+    token_response(&state, user_id, refresh_token)
+}
+
+/// Revokes a refresh token and every token rotated from the same sign-in.
+/// Access tokens already issued stay valid until they expire.
+pub async fn logout(
+    State(state): State<AppState>,
+    ValidatedJson(payload): ValidatedJson<RefreshRequest>,
+) -> Result<StatusCode, AppError> {
+    refresh::revoke(&state.db, &payload.refresh_token).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Emails a password reset link if `email` belongs to a user. The lookup
+/// and mail happen in the background, so the response is the same `202`,
+/// just as fast, whether or not the account exists.
+pub async fn request_password_reset(
+    State(state): State<AppState>,
+    ValidatedJson(payload): ValidatedJson<PasswordResetRequest>,
+) -> StatusCode {
+    // Repeats would be skipped anyway; dropping them here keeps a flood
+    // for one address from crowding out everyone else's requests
+    if !state
+        .password_reset_throttle
+        .allow(&payload.email.trim().to_lowercase())
+    {
+        tracing::info!("password reset requested again too soon; ignored");
+        return StatusCode::ACCEPTED;
+    }
+
+    let background = state.background.clone();
+    let started = background.spawn(async move {
+        if let Err(e) = send_password_reset(&state, &payload.email).await {
+            tracing::error!("failed to start password reset: {e:?}");
+        }
+    });
+    if !started {
+        tracing::warn!("too many password resets in progress; dropped a request");
+    }
+
+    StatusCode::ACCEPTED
+}
+
+async fn send_password_reset(state: &AppState, email: &str) -> Result<(), AppError> {
+    let user = user::Entity::find()
+        .filter(lower_email().eq(email.to_lowercase()))
+        .filter(user::Column::DeletedAt.is_null())
+        .one(&state.db)
+        .await?;
+
+    match user {
+        Some(user) => password_reset::send(state, &user).await,
+        None => {
+            tracing::info!("password reset requested for unknown email");
+            Ok(())
+        }
+    }
+}
+
+/// Sets a new password with the token from a reset link, then signs the
+/// user out everywhere: refresh tokens and sessions are revoked.
+pub async fn confirm_password_reset(
+    State(state): State<AppState>,
+    ValidatedJson(payload): ValidatedJson<ConfirmPasswordResetRequest>,
+) -> Result<StatusCode, AppError> {
+    let txn = state.db.begin().await?;
+
+    let user = password_reset::redeem(&txn, &payload.token).await?;
+
+    let password_hash = state.passwords.hash(payload.new_password).await?;
+    let user_id = user.id;
+    let mut user: user::ActiveModel = user.into();
+    user.password_hash = Set(Some(password_hash));
+    user.updated_at = Set(chrono::Utc::now().naive_utc());
+    user.update(&txn).await?;
+
+    refresh::revoke_all(&txn, user_id).await?;
+    session::revoke_all(&txn, user_id).await?;
+
+    txn.commit().await?;
+
+    tracing::info!(
+        user_id,
+        "reset password, revoked sessions and refresh tokens"
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Signs in with an email and password, starting a cookie session, or
+/// returns a challenge if the user has two-factor authentication enabled.
+pub async fn create_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<LoginRequest>,
+) -> Result<Response, AppError> {
+    session::check_origin(&headers, &state.trusted_origins)?;
+
+    let user = check_credentials(&state, payload).await?;
+
+    if let Some(challenge) = mfa_challenge(&state, user.id).await? {
+        return Ok(challenge.into_response());
+    }
+
+    start_session(&state, &headers, user.id).await
+}
+
+/// Completes a sign-in with a second factor, starting a cookie session.
+pub async fn create_session_mfa(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ValidatedJson(payload): ValidatedJson<MfaLoginRequest>,
+) -> Result<Response, AppError> {
+    session::check_origin(&headers, &state.trusted_origins)?;
+
+    let user_id = check_second_factor(&state, payload).await?;
+
+    start_session(&state, &headers, user_id).await
+}
+
+/// Creates a session for `user_id` and sets its cookies.
+async fn start_session(
+    state: &AppState,
+    headers: &HeaderMap,
+    user_id: i32,
+) -> Result<Response, AppError> {
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok());
+    let new_session = session::create(&state.db, &state.session, user_id, user_agent).await?;
+
+    tracing::info!(user_id, session_id = new_session.id, "started session");
+
+    let [session_cookie, csrf_cookie] = session::cookies(&state.session, &new_session);
+    Ok((
+        StatusCode::CREATED,
+        AppendHeaders([
+            (header::SET_COOKIE, session_cookie),
+            (header::SET_COOKIE, csrf_cookie),
+        ]),
+        Json(NewSessionResponse {
+            id: new_session.id,
+            csrf_token: new_session.csrf_token,
+            expires_at: new_session.expires_at.to_string(),
+        }),
+    )
+        .into_response())
+}
+
+/// Ends the session the request was made with and clears its cookies.
+pub async fn delete_session(
+    State(state): State<AppState>,
+    AllowUnenrolled(principal): AllowUnenrolled,
+) -> Result<Response, AppError> {
+    let (Some(user_id), Some(session_id)) = (principal.user_id, principal.session_id) else {
+        return Err(AppError::BadRequest(
+            "request was not made with a session".to_string(),
+        ));
+    };
+
+    session::revoke(&state.db, user_id, session_id).await?;
+
+    let [session_cookie, csrf_cookie] = session::clear_cookies(&state.session);
+    Ok((
+        StatusCode::NO_CONTENT,
+        AppendHeaders([
+            (header::SET_COOKIE, session_cookie),
+            (header::SET_COOKIE, csrf_cookie),
+        ]),
+    )
+        .into_response())
+}
+
+/// Lists the caller's open sessions, so they can spot and end unknown ones.
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<SessionResponse>>, AppError> {
+    let user_id = principal.require_signed_in_user()?;
+
+    let sessions = session::list_active(&state.db, &state.session, user_id).await?;
+
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|s| SessionResponse {
+                current: principal.session_id == Some(s.id),
+                id: s.id,
+                user_agent: s.user_agent,
+                created_at: s.created_at.to_string(),
+                last_seen_at: s.last_seen_at.to_string(),
+                expires_at: s.expires_at.to_string(),
+            })
+            .collect(),
+    ))
+}
+
+/// Ends one of the caller's sessions, e.g. on a lost device.
+pub async fn revoke_session(
+    State(state): State<AppState>,
+    principal: Principal,
+    AppPath(id): AppPath<i64>,
+) -> Result<StatusCode, AppError> {
+    let user_id = principal.require_signed_in_user()?;
+
+    if !session::revoke(&state.db, user_id, id).await? {
+        return Err(AppError::NotFound(format!("session {id} not found")));
+    }
+
+    tracing::info!(user_id, session_id = id, "revoked session");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Soft-deletes a user: the row is kept with `deleted_at` set and hidden
+/// from reads until restored or purged. Admins only; members can't delete
+/// even their own account.
+pub async fn delete_user(
+    State(state): State<AppState>,
+    principal: Principal,
+    AppPath(id): AppPath<i32>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    principal.require_admin()?;
+
+    let txn = state.db.begin().await?;
+
+    let user = find_user_for_update(&txn, id, &headers).await?;
+
+    let now = chrono::Utc::now().naive_utc();
+    let mut user: user::ActiveModel = user.into();
+    user.deleted_at = Set(Some(now));
+    user.updated_at = Set(now);
+
+    user.update(&txn).await?;
+
+    txn.commit().await?;
+
+    tracing::info!(
+        user_id = id,
+        deleted_by = principal.user_id,
+        "soft-deleted user"
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn restore_user(
+    State(state): State<AppState>,
+    _admin: RequireAdmin,
+    AppPath(id): AppPath<i32>,
+) -> Result<Response, AppError> {
+    let txn = state.db.begin().await?;
+
+    let user = user::Entity::find_by_id(id)
+        .filter(user::Column::DeletedAt.is_not_null())
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("deleted user {id} not found")))?;
+
+    let mut user: user::ActiveModel = user.into();
+    user.deleted_at = Set(None);
+    user.updated_at = Set(chrono::Utc::now().naive_utc());
+
+    // Fails with 409 if another live user has claimed the email meanwhile.
+    let user = user.update(&txn).await?;
+
+    txn.commit().await?;
+
+    Ok(user_response(user))
+}
+
+/// Permanently removes a user, whether or not it was soft-deleted first.
+pub async fn purge_user(
+    State(state): State<AppState>,
+    _admin: RequireAdmin,
+    AppPath(id): AppPath<i32>,
+) -> Result<StatusCode, AppError> {
+    let result = user::Entity::delete_by_id(id).exec(&state.db).await?;
+
+    if result.rows_affected == 0 {
+        return Err(AppError::user_not_found(id));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Creates an API key acting as the signed-in user.
+pub async fn create_api_key(
+    State(state): State<AppState>,
+    principal: Principal,
+    ValidatedJson(payload): ValidatedJson<CreateApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreatedApiKeyResponse>), AppError> {
+    let user_id = principal.require_signed_in_user()?;
+
+    let mut scopes: Vec<String> = payload
+        .scopes
+        .iter()
+        .map(|scope| scope.as_str().to_string())
+        .collect();
+    scopes.sort();
+    scopes.dedup();
+
+    let new_key = api_key::generate();
+    let model = api_key_entity::ActiveModel {
+        user_id: Set(user_id),
+        name: Set(payload.name),
+        prefix: Set(new_key.prefix),
+        key_hash: Set(new_key.hash),
+        scopes: Set(scopes),
+        created_at: Set(chrono::Utc::now().naive_utc()),
+        expires_at: Set(payload.expires_at.map(|t| t.naive_utc())),
+        ..Default::default()
+    }
+    .insert(&state.db)
+    .await?;
+
+    tracing::info!(user_id, api_key_id = model.id, "created API key");
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiKeyResponse {
+            api_key: model.into(),
+            key: new_key.key,
+        }),
+    ))
+}
+
+/// Lists the signed-in user's API keys, including revoked ones.
+pub async fn list_api_keys(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<ApiKeyResponse>>, AppError> {
+    let user_id = principal.require_signed_in_user()?;
+
+    let keys = api_key_entity::Entity::find()
+        .filter(api_key_entity::Column::UserId.eq(user_id))
+        .order_by_asc(api_key_entity::Column::Id)
+        .all(&state.db)
+        .await?;
+
+    Ok(Json(keys.into_iter().map(Into::into).collect()))
+}
+
+/// Revokes one of the signed-in user's API keys; admins may revoke anyone's.
+pub async fn revoke_api_key(
+    State(state): State<AppState>,
+    principal: Principal,
+    AppPath(id): AppPath<i64>,
+) -> Result<StatusCode, AppError> {
+    // Admins, including the admin token, may revoke any key
+    let owner = if principal.is_admin() && principal.scopes.is_none() {
+        None
+    } else {
+        Some(principal.require_signed_in_user()?)
+    };
+
+    let mut update = api_key_entity::Entity::update_many()
+        .col_expr(
+            api_key_entity::Column::RevokedAt,
+            Expr::value(chrono::Utc::now().naive_utc()),
+        )
+        .filter(api_key_entity::Column::Id.eq(id))
+        .filter(api_key_entity::Column::RevokedAt.is_null());
+    if let Some(owner) = owner {
+        update = update.filter(api_key_entity::Column::UserId.eq(owner));
+    }
+
+    if update.exec(&state.db).await?.rows_affected == 0 {
+        return Err(AppError::NotFound(format!("API key {id} not found")));
+    }
+
+    tracing::info!(
+        revoked_by = principal.user_id,
+        api_key_id = id,
+        "revoked API key"
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Starts setting up two-factor authentication for the caller's own
+/// account, returning the secret for their authenticator app.
+pub async fn enroll_totp(
+    State(state): State<AppState>,
+    AllowUnenrolled(principal): AllowUnenrolled,
+    AppPath(id): AppPath<i32>,
+) -> Result<(StatusCode, Json<Enrollment>), AppError> {
+    principal.require_own_account(id)?;
+
+    let txn = state.db.begin().await?;
+
+    let user = user::Entity::find_by_id(id)
+        .filter(user::Column::DeletedAt.is_null())
+        .one(&txn)
+        .await?
+        .ok_or_else(|| AppError::user_not_found(id))?;
+    let enrollment = totp::enroll(&txn, &state.totp.issuer, &user).await?;
+
+    txn.commit().await?;
+
+    Ok((StatusCode::CREATED, Json(enrollment)))
+}
+
+/// Enables two-factor authentication with a first code from the app,
+/// returning one-time recovery codes. They are only shown here. Other
+/// sessions and all refresh tokens are revoked, so sign-ins made before
+/// have to pass the second factor too.
+pub async fn confirm_totp(
+    State(state): State<AppState>,
+    AllowUnenrolled(principal): AllowUnenrolled,
+    AppPath(id): AppPath<i32>,
+    ValidatedJson(payload): ValidatedJson<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    principal.require_own_account(id)?;
+
+    let txn = state.db.begin().await?;
+    let recovery_codes = totp::confirm(&txn, id, payload.code.trim()).await?;
+    refresh::revoke_all(&txn, id).await?;
+    session::revoke_all_except(&txn, id, principal.session_id).await?;
+    txn.commit().await?;
+
+    tracing::info!(user_id = id, "enabled two-factor authentication");
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Turns off two-factor authentication. Users turning off their own must
+/// send a current TOTP or recovery code; admins can turn off anyone
+/// else's without one, e.g. for a user who lost their device. API keys
+/// can't do either. Other sessions and all refresh tokens are revoked.
+pub async fn disable_totp(
+    State(state): State<AppState>,
+    principal: Principal,
+    AppPath(id): AppPath<i32>,
+    payload: Option<ValidatedJson<TotpCodeRequest>>,
+) -> Result<StatusCode, AppError> {
+    let by_admin = principal.is_admin() && principal.user_id != Some(id);
+    if principal.scopes.is_some() || !by_admin {
+        principal.require_own_account(id)?;
+        if !totp::is_enabled(&state.db, id).await? {
+            return Err(totp_not_enabled(id));
+        }
+        let Some(ValidatedJson(payload)) = payload else {
+            return Err(AppError::BadRequest(
+                "a current two-factor or recovery code is required".to_string(),
+            ));
+        };
+        totp::check(&state.db, id, &payload.code).await?;
+    }
+
+    let txn = state.db.begin().await?;
+    let disabled = totp::disable(&txn, id).await?;
+    if disabled {
+        let keep = if by_admin { None } else { principal.session_id };
+        refresh::revoke_all(&txn, id).await?;
+        session::revoke_all_except(&txn, id, keep).await?;
+    }
+    txn.commit().await?;
+
+    if !disabled {
+        return Err(totp_not_enabled(id));
+    }
+
+    tracing::info!(
+        user_id = id,
+        disabled_by = principal.user_id,
+        "disabled two-factor authentication"
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn totp_not_enabled(id: i32) -> AppError {
+    AppError::NotFound(format!(
+        "user {id} does not have two-factor authentication enabled"
+    ))
+}
+
+/// Lists the roles whose users must enable two-factor authentication.
+pub async fn list_mfa_required_roles(
+    State(state): State<AppState>,
+    _: RequireAdmin,
+) -> Result<Json<MfaRequiredRolesResponse>, AppError> {
+    Ok(Json(MfaRequiredRolesResponse {
+        roles: totp::required_roles(&state.db).await?,
+    }))
+}
+
+/// Requires users of a role to enable two-factor authentication. Until
+/// they do, they can only enroll.
+pub async fn require_mfa_for_role(
+    State(state): State<AppState>,
+    _: RequireAdmin,
+    AppPath(role): AppPath<Role>,
+) -> Result<StatusCode, AppError> {
+    totp::set_required(&state.db, role, true).await?;
+
+    tracing::info!(?role, "required two-factor authentication");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn unrequire_mfa_for_role(
+    State(state): State<AppState>,
+    _: RequireAdmin,
+    AppPath(role): AppPath<Role>,
+) -> Result<StatusCode, AppError> {
+    totp::set_required(&state.db, role, false).await?;
+
+    tracing::info!(?role, "no longer requiring two-factor authentication");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        http::Request,
+        routing::{delete, patch, post},
+        Router,
+    };
+    use sea_orm::{ActiveEnum, ProxyRow, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        auth::token,
+        entities::password_reset_token,
+        mail::MemoryMailer,
+        testing::{count, database, model_row, row, state, Script},
+    };
+
+    fn user_model(id: i32, role: Role) -> user::Model {
+        let now = Utc::now().naive_utc();
+        user::Model {
+            id,
+            name: "Jane".to_string(),
+            email: "jane@example.com".to_string(),
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            deleted_at: None,
+            password_hash: None,
+            role,
+            email_verified_at: None,
+            email_verification_nonce: None,
+        }
+    }
+
+    fn user_row(id: i32, role: Role) -> ProxyRow {
+        model_row(&user_model(id, role))
+    }
+
+    /// Rows read while authenticating a user with `role`: the role itself,
+    /// then whether the role requires a second factor.
+    fn authenticated_as(role: Role) -> Vec<Vec<ProxyRow>> {
+        vec![
+            vec![row([("role", Value::from(role.to_value()))])],
+            vec![count(0)],
+        ]
+    }
+
+    async fn send_delete(state: AppState, uri: &str, header: &str, value: String) -> StatusCode {
+        let app = Router::new()
+            .route("/users/{id}", delete(delete_user))
+            .route("/api-keys/{id}", delete(revoke_api_key))
+            .with_state(state);
+        let request = Request::delete(uri)
+            .header(header, value)
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    async fn delete_user_7(state: AppState, header: &str, value: String) -> StatusCode {
+        send_delete(state, "/users/7", header, value).await
+    }
+
+    async fn change_password_7(state: AppState, token: String, body: &str) -> StatusCode {
+        let app = Router::new()
+            .route("/users/{id}/password", post(change_password))
+            .with_state(state);
+        let request = Request::post("/users/7/password")
+            .header("authorization", format!("Bearer {token}"))
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn members_cannot_delete_their_own_account() {
+        let state = state(database(authenticated_as(Role::Member)).await);
+        let token = state.jwt.issue(7).unwrap();
+
+        let status = delete_user_7(state, "authorization", format!("Bearer {token}")).await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn admins_can_delete_users() {
+        let mut results = authenticated_as(Role::Admin);
+        results.push(vec![user_row(7, Role::Member)]);
+        results.push(vec![user_row(7, Role::Member)]);
+        let state = state(database(results).await);
+        let token = state.jwt.issue(1).unwrap();
+
+        let status = delete_user_7(state, "authorization", format!("Bearer {token}")).await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn the_admin_token_can_delete_users() {
+        let results = vec![
+            vec![user_row(7, Role::Member)],
+            vec![user_row(7, Role::Member)],
+        ];
+        let state = state(database(results).await);
+
+        let status = delete_user_7(state, "x-admin-token", "admin-token".to_string()).await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn the_admin_token_can_revoke_api_keys() {
+        let state = state(database(vec![]).await);
+
+        let status = send_delete(state, "/api-keys/3", "x-admin-token", "admin-token".into()).await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn admins_must_confirm_their_own_current_password() {
+        let mut results = authenticated_as(Role::Admin);
+        results.push(vec![user_row(7, Role::Admin)]);
+        let state = state(database(results).await);
+        let token = state.jwt.issue(7).unwrap();
+
+        let body = r#"{"new_password":"a new long password"}"#;
+        let status = change_password_7(state, token, body).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn resetting_a_password_signs_the_user_out_everywhere() {
+        let now = Utc::now().naive_utc();
+        let token = password_reset_token::Model {
+            id: 1,
+            user_id: 7,
+            token_hash: token::hash("reset-token"),
+            created_at: now,
+            expires_at: now + chrono::Duration::hours(1),
+            used_at: None,
+        };
+        let script = Script::new(vec![
+            vec![model_row(&token)],
+            vec![model_row(&token)],
+            vec![user_row(7, Role::Member)],
+            vec![user_row(7, Role::Member)],
+        ]);
+        let app = Router::new()
+            .route("/auth/password-reset/confirm", post(confirm_password_reset))
+            .with_state(state(script.connect().await));
+        let request = Request::post("/auth/password-reset/confirm")
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"token":"reset-token","new_password":"a new long password"}"#,
+            ))
+            .unwrap();
+
+        let status = app.oneshot(request).await.unwrap().status();
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(script.ran(&["UPDATE \"users\"", "\"password_hash\""]));
+        assert!(script.ran(&[
+            "UPDATE \"refresh_tokens\"",
+            "\"revoked_at\"",
+            "\"user_id\" = 7"
+        ]));
+        assert!(script.ran(&["UPDATE \"sessions\"", "\"revoked_at\"", "\"user_id\" = 7"]));
+    }
+
+    async fn post_json(app: Router, uri: &str, body: &str) -> StatusCode {
+        let request = Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn verification_links_work_once() {
+        let mut pending = user_model(7, Role::Member);
+        let mut verified = pending.clone();
+        verified.email_verified_at = Some(Utc::now().naive_utc());
+        let script = Script::new(vec![vec![user_row(7, Role::Member)]]);
+        let outbox = MemoryMailer::default();
+        let state = AppState {
+            mailer: Arc::new(outbox.clone()),
+            ..state(script.connect().await)
+        };
+        let app = Router::new()
+            .route("/users", post(create_user))
+            .route("/users/verify-email", post(verify_email))
+            .with_state(state.clone());
+
+        let body = r#"{"name":"Jane","email":"jane@example.com","password":"a long password"}"#;
+        assert_eq!(
+            post_json(app.clone(), "/users", body).await,
+            StatusCode::CREATED
+        );
+
+        let sent = outbox.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].to, "jane@example.com");
+        let (_, link) = sent[0].body.split_once("token=").unwrap();
+        let token = link.split_whitespace().next().unwrap();
+        let (_, claims) = verify_email::verify(&state, token).unwrap();
+        assert!(script.ran(&["UPDATE \"users\"", &claims.nonce]));
+
+        // What the database holds once the link is out
+        pending.email_verification_nonce = Some(claims.nonce);
+        let script = Script::new(vec![
+            vec![model_row(&pending)],
+            vec![model_row(&verified)],
+            vec![model_row(&verified)],
+        ]);
+        let app = Router::new()
+            .route("/users/verify-email", post(verify_email))
+            .with_state(AppState {
+                db: Traced(script.connect().await),
+                ..state
+            });
+        let body = format!(r#"{{"token":"{token}"}}"#);
+
+        let first = post_json(app.clone(), "/users/verify-email", &body).await;
+        let second = post_json(app, "/users/verify-email", &body).await;
+
+        assert_eq!(first, StatusCode::OK);
+        assert!(script.ran(&["UPDATE \"users\"", "\"email_verification_nonce\" = NULL"]));
+        assert_eq!(second, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn verification_emails_are_resent_at_most_once_a_minute() {
+        let mut results = authenticated_as(Role::Member);
+        results.push(vec![user_row(7, Role::Member)]);
+        results.extend(authenticated_as(Role::Member));
+        results.push(vec![user_row(7, Role::Member)]);
+        let outbox = MemoryMailer::default();
+        let state = AppState {
+            mailer: Arc::new(outbox.clone()),
+            ..state(database(results).await)
+        };
+        let token = state.jwt.issue(7).unwrap();
+        let app = Router::new()
+            .route(
+                "/users/{id}/verification-email",
+                post(resend_verification_email),
+            )
+            .with_state(state);
+        let resend = || {
+            Request::post("/users/7/verification-email")
+                .header("authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let first = app.clone().oneshot(resend()).await.unwrap().status();
+        let second = app.oneshot(resend()).await.unwrap().status();
+
+        assert_eq!(first, StatusCode::ACCEPTED);
+        assert_eq!(second, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(outbox.sent().len(), 1);
+    }
+
+    /// Patches user 7 as the admin token, so only the handler's own
+    /// queries run.
+    async fn patch_user_7(
+        results: Vec<Vec<ProxyRow>>,
+        content_type: &str,
+        if_match: Option<&str>,
+        body: &str,
+    ) -> (StatusCode, Script) {
+        let script = Script::new(results);
+        let app = Router::new()
+            .route("/users/{id}", patch(patch_user))
+            .with_state(state(script.connect().await));
+        let mut request = Request::patch("/users/7")
+            .header("x-admin-token", "admin-token")
+            .header("content-type", content_type);
+        if let Some(etag) = if_match {
+            request = request.header("if-match", etag);
+        }
+        let request = request.body(Body::from(body.to_string())).unwrap();
+
+        let status = app.oneshot(request).await.unwrap().status();
+        (status, script)
+    }
+
+    fn found_and_updated() -> Vec<Vec<ProxyRow>> {
+        vec![
+            vec![user_row(7, Role::Member)],
+            vec![user_row(7, Role::Member)],
+        ]
+    }
+
+    #[tokio::test]
+    async fn merge_patches_replace_the_given_fields() {
+        let (status, script) = patch_user_7(
+            found_and_updated(),
+            MERGE_PATCH_CONTENT_TYPE,
+            None,
+            r#"{"name":"Janet"}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(script.ran(&["UPDATE \"users\"", "'Janet'", "'jane@example.com'"]));
+    }
+
+    #[tokio::test]
+    async fn json_patches_apply_their_operations_in_order() {
+        let body = r#"[
+            {"op":"test","path":"/name","value":"Jane"},
+            {"op":"replace","path":"/name","value":"Janet"}
+        ]"#;
+
+        let (status, script) =
+            patch_user_7(found_and_updated(), JSON_PATCH_CONTENT_TYPE, None, body).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(script.ran(&["UPDATE \"users\"", "'Janet'"]));
+    }
+
+    #[tokio::test]
+    async fn failed_json_patch_tests_change_nothing() {
+        let body = r#"[
+            {"op":"test","path":"/name","value":"John"},
+            {"op":"replace","path":"/name","value":"Janet"}
+        ]"#;
+
+        let (status, script) = patch_user_7(
+            vec![vec![user_row(7, Role::Member)]],
+            JSON_PATCH_CONTENT_TYPE,
+            None,
+            body,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(!script.ran(&["UPDATE \"users\""]));
+    }
+
+    #[tokio::test]
+    async fn patches_cannot_add_unknown_fields() {
+        let (status, script) = patch_user_7(
+            vec![vec![user_row(7, Role::Member)]],
+            MERGE_PATCH_CONTENT_TYPE,
+            None,
+            r#"{"role":"admin"}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(!script.ran(&["UPDATE \"users\""]));
+    }
+
+    #[tokio::test]
+    async fn stale_if_match_headers_are_rejected() {
+        let (status, script) = patch_user_7(
+            vec![vec![user_row(7, Role::Member)]],
+            MERGE_PATCH_CONTENT_TYPE,
+            Some("\"0\""),
+            r#"{"name":"Janet"}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        assert!(!script.ran(&["UPDATE \"users\""]));
+    }
+
+    #[tokio::test]
+    async fn current_if_match_headers_are_accepted() {
+        let etag = etag::for_user(&user_model(7, Role::Member));
+
+        let (status, _) = patch_user_7(
+            found_and_updated(),
+            MERGE_PATCH_CONTENT_TYPE,
+            Some(&etag),
+            r#"{"name":"Janet"}"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unusable_patches_are_rejected_before_locking_the_user() {
+        for (content_type, body, expected) in [
+            (
+                "application/json",
+                r#"{"name":"Janet"}"#,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (MERGE_PATCH_CONTENT_TYPE, "{", StatusCode::BAD_REQUEST),
+            (
+                JSON_PATCH_CONTENT_TYPE,
+                r#"{"op":"add"}"#,
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let (status, script) = patch_user_7(vec![], content_type, None, body).await;
+
+            assert_eq!(status, expected, "{content_type} {body}");
+            assert!(script.statements().is_empty(), "{content_type} {body}");
+        }
+    }
+
+    #[test]
+    fn readiness_hides_database_error_details() {
+        let err = DbErr::Custom("password authentication failed for user \"app\"".into());
+
+        let check = Check::database_error("database", err);
+
+        assert!(!check.is_ok());
+        assert_eq!(check.error.as_deref(), Some("database error"));
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like("jane"), "jane");
+        assert_eq!(escape_like("100%_off"), "100\\%\\_off");
+        assert_eq!(escape_like("a\\b"), "a\\\\b");
+    }
+}
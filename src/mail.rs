@@ -0,0 +1,183 @@
+//! Outbound email.
+//!
+//! Handlers send through the [`Mailer`] in `AppState`. The sinks here don't
+//! need a mail server: `log` writes messages to the log and `file` saves
+//! each one as an `.eml` file. Tests use [`MemoryMailer`] to inspect them.
+
+#[cfg(test)]
+use std::sync::Mutex;
+use std::{path::PathBuf, sync::Arc};
+
+use crate::config::{MailConfig, MailSink};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, message: Message) -> Result<(), String>;
+}
+
+/// `url` with `token` appended as the `token` query parameter, for links
+/// to frontend pages. Tokens must be URL-safe.
+pub fn link(url: &str, token: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{url}{separator}token={token}")
+}
+
+/// Builds the sink selected by `mail.sink`.
+pub fn from_config(config: &MailConfig) -> Arc<dyn Mailer> {
+    match config.sink {
+        MailSink::Log => Arc::new(LogMailer),
+        MailSink::File => Arc::new(FileMailer::new(&config.dir, &config.from)),
+    }
+}
+
+/// Logs messages at info level instead of delivering them.
+pub struct LogMailer;
+
+#[async_trait::async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, message: Message) -> Result<(), String> {
+        tracing::info!(
+            to = %message.to,
+            subject = %message.subject,
+            body = %message.body,
+            "mail not delivered, log sink configured"
+        );
+        Ok(())
+    }
+}
+
+/// Writes each message to `<dir>/<timestamp>-<uuid>.eml`.
+pub struct FileMailer {
+    dir: PathBuf,
+    from: String,
+}
+
+impl FileMailer {
+    pub fn new(dir: &str, from: &str) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            from: from.to_string(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, message: Message) -> Result<(), String> {
+        let now = chrono::Utc::now();
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            now.format("%Y%m%dT%H%M%S%.6fZ"),
+            uuid::Uuid::new_v4()
+        ));
+        let contents = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            self.from,
+            message.to,
+            message.subject,
+            now.to_rfc2822(),
+            message.body.replace('\n', "\r\n"),
+        );
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| format!("cannot create {}: {e}", self.dir.display()))?;
+        tokio::fs::write(&path, contents)
+            .await
+            .map_err(|e| format!("cannot write {}: {e}", path.display()))
+    }
+}
+
+/// Keeps sent messages in memory. Clones share the same outbox.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MemoryMailer {
+    sent: Arc<Mutex<Vec<Message>>>,
+}
+
+#[cfg(test)]
+impl MemoryMailer {
+    /// Messages sent so far, oldest first.
+    pub fn sent(&self) -> Vec<Message> {
+        self.sent.lock().expect("outbox lock poisoned").clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, message: Message) -> Result<(), String> {
+        self.sent
+            .lock()
+            .expect("outbox lock poisoned")
+            .push(message);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> Message {
+        Message {
+            to: "jane@example.com".to_string(),
+            subject: "Hello".to_string(),
+            body: "line one\nline two".to_string(),
+        }
+    }
+
+    #[test]
+    fn link_appends_the_token() {
+        assert_eq!(
+            link("https://app.example.com/verify", "a.b.c"),
+            "https://app.example.com/verify?token=a.b.c"
+        );
+        assert_eq!(
+            link("https://app.example.com/verify?lang=en", "a.b.c"),
+            "https://app.example.com/verify?lang=en&token=a.b.c"
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_mailer_keeps_messages_in_order() {
+        let mailer = MemoryMailer::default();
+        let outbox = mailer.clone();
+        mailer.send(message()).await.unwrap();
+        mailer
+            .send(Message {
+                subject: "Again".to_string(),
+                ..message()
+            })
+            .await
+            .unwrap();
+
+        let sent = outbox.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0], message());
+        assert_eq!(sent[1].subject, "Again");
+    }
+
+    #[tokio::test]
+    async fn file_mailer_writes_one_eml_per_message() {
+        let dir = std::env::temp_dir().join(format!("mail-test-{}", uuid::Uuid::new_v4()));
+        let mailer = FileMailer::new(dir.to_str().unwrap(), "noreply@example.com");
+        mailer.send(message()).await.unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let contents = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(contents.starts_with("From: noreply@example.com\r\nTo: jane@example.com\r\n"));
+        assert!(contents.contains("Subject: Hello\r\n"));
+        assert!(contents.ends_with("\r\n\r\nline one\r\nline two\r\n"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,69 +1,312 @@
+mod auth;
+mod background;
+mod config;
+mod db;
 mod entities;
+mod error;
+mod etag;
+mod extract;
 mod handlers;
+mod mail;
+mod metrics;
+mod migration;
+mod pagination;
+mod password;
+mod request_id;
+mod shutdown;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+mod throttle;
+mod validation;
 
 use axum::{
-    routing::{get, post, put, delete},
+    http::{header, Method, StatusCode},
+    middleware,
+    routing::{delete, get, patch, post, put},
     Router,
 };
-use sea_orm::{Database, DatabaseConnection};
-use std::env;
-use tower_http::trace::TraceLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use clap::Parser;
+use sea_orm::DatabaseConnection;
+use std::{future::IntoFuture, sync::Arc, time::Duration};
+use tower_http::{
+    cors::{AllowOrigin, Any, CorsLayer},
+    timeout::TimeoutLayer,
+    trace::TraceLayer,
+};
+
+use auth::jwt::Jwt;
+use background::Background;
+use config::{Cli, Command, Config, SessionConfig, TotpConfig};
+use db::Traced;
+use mail::Mailer;
+use metrics::Metrics;
+use password::PasswordHasher;
+use shutdown::ShutdownFlag;
+use throttle::Throttle;
+
+/// Background tasks allowed to run at once; more are dropped.
+const BACKGROUND_TASKS: u32 = 64;
 
 #[derive(Clone)]
 pub struct AppState {
-    db: DatabaseConnection,
+    db: Traced<DatabaseConnection>,
+    admin_token: Option<String>,
+    shutdown: ShutdownFlag,
+    health_check_timeout: Duration,
+    metrics: Metrics,
+    passwords: PasswordHasher,
+    jwt: Jwt,
+    session: SessionConfig,
+    /// Origins whose pages may sign in with a session: the CORS origins.
+    trusted_origins: Vec<String>,
+    mailer: Arc<dyn Mailer>,
+    email_verification_ttl: Duration,
+    verify_email_url: String,
+    password_reset_ttl: Duration,
+    reset_password_url: String,
+    /// Sheds repeated reset requests per email before they take up a
+    /// background task.
+    password_reset_throttle: Throttle,
+    /// Limits how often each user can have a verification link resent.
+    verification_throttle: Throttle,
+    totp: TotpConfig,
+    background: Background,
 }
 
 #[tokio::main]
 async fn main() {
-    // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "axum_seaorm=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
     // Load environment variables
     dotenvy::dotenv().ok();
 
-    // Get database URL from environment
-    let database_url = env::var("DATABASE_URL")
-        .expect("DATABASE_URL must be set in environment");
+    // Load and validate configuration, reporting every problem at once
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(errors) => {
+            eprint!("{errors}");
+            std::process::exit(2);
+        }
+    };
+
+    // Initialize logging and optional trace export
+    let telemetry = match telemetry::init(&config) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
 
     // Connect to database
     tracing::info!("Connecting to database...");
-    let db = Database::connect(&database_url)
+    let mut db = db::connect(&config.database)
         .await
         .expect("Failed to connect to database");
 
     tracing::info!("Database connected successfully");
 
+    let metrics = Metrics::new().expect("Failed to register metrics");
+    let query_metrics = metrics.clone();
+    db.set_metric_callback(move |info| query_metrics.observe_query(info));
+
+    // `migrate <up|down|status>` manages the schema and exits
+    if let Some(Command::Migrate { action }) = &cli.command {
+        if let Err(e) = migration::command(&db, action).await {
+            eprintln!("Migration failed: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Bring the schema up to date before serving requests
+    tracing::info!("Running database migrations...");
+    migration::run_pending(&db)
+        .await
+        .expect("Failed to run database migrations");
+
+    if config.auth.admin_token.is_none() {
+        tracing::warn!("No admin token configured, admin endpoints are disabled");
+    }
+
+    entities::user::set_lowercase_email_local_part(config.users.lowercase_email_local_part);
+
     // Create application state
-    let state = AppState { db };
+    let state = AppState {
+        db: Traced(db.clone()),
+        admin_token: config.auth.admin_token.clone(),
+        shutdown: ShutdownFlag::default(),
+        health_check_timeout: config.database.health_check_timeout,
+        metrics,
+        // Parameters were validated when the config was loaded
+        passwords: PasswordHasher::new(&config.password).expect("valid password settings"),
+        // Keys were loaded and checked when the config was loaded
+        jwt: Jwt::new(&config.jwt).expect("valid JWT settings"),
+        session: config.session.clone(),
+        trusted_origins: config.server.cors_origins.clone(),
+        mailer: mail::from_config(&config.mail),
+        email_verification_ttl: config.users.email_verification_ttl,
+        verify_email_url: config.mail.verify_email_url.clone(),
+        password_reset_ttl: config.users.password_reset_ttl,
+        reset_password_url: config.mail.reset_password_url.clone(),
+        password_reset_throttle: Throttle::new(auth::password_reset::RESEND_INTERVAL),
+        verification_throttle: Throttle::new(auth::verify_email::RESEND_INTERVAL),
+        totp: config.totp.clone(),
+        background: Background::new(BACKGROUND_TASKS),
+    };
+    let shutdown = state.shutdown.clone();
+    let background = state.background.clone();
 
     // Build router
-    let app = Router::new()
-        .route("/", get(handlers::health_check))
+    let mut app = Router::new()
+        .route("/", get(handlers::liveness))
+        .route("/health/live", get(handlers::liveness))
+        .route("/health/ready", get(handlers::readiness))
         .route("/users", post(handlers::create_user))
         .route("/users", get(handlers::list_users))
         .route("/users/{id}", get(handlers::get_user))
         .route("/users/{id}", put(handlers::update_user))
+        .route("/users/{id}", patch(handlers::patch_user))
         .route("/users/{id}", delete(handlers::delete_user))
-        .layer(TraceLayer::new_for_http())
+        .route("/users/{id}/restore", post(handlers::restore_user))
+        .route("/users/{id}/purge", post(handlers::purge_user))
+        .route("/users/{id}/password", post(handlers::change_password))
+        .route("/users/{id}/role", put(handlers::change_role))
+        .route(
+            "/users/{id}/verification-email",
+            post(handlers::resend_verification_email),
+        )
+        .route("/users/verify-email", post(handlers::verify_email))
+        .route("/users/{id}/totp", post(handlers::enroll_totp))
+        .route("/users/{id}/totp", delete(handlers::disable_totp))
+        .route("/users/{id}/totp/confirm", post(handlers::confirm_totp))
+        .route(
+            "/mfa/required-roles",
+            get(handlers::list_mfa_required_roles),
+        )
+        .route(
+            "/mfa/required-roles/{role}",
+            put(handlers::require_mfa_for_role),
+        )
+        .route(
+            "/mfa/required-roles/{role}",
+            delete(handlers::unrequire_mfa_for_role),
+        )
+        .route("/auth/login", post(handlers::login))
+        .route("/auth/login/mfa", post(handlers::login_mfa))
+        .route("/auth/refresh", post(handlers::refresh))
+        .route("/auth/logout", post(handlers::logout))
+        .route(
+            "/auth/password-reset/request",
+            post(handlers::request_password_reset),
+        )
+        .route(
+            "/auth/password-reset/confirm",
+            post(handlers::confirm_password_reset),
+        )
+        .route("/auth/session", post(handlers::create_session))
+        .route("/auth/session/mfa", post(handlers::create_session_mfa))
+        .route("/auth/session", delete(handlers::delete_session))
+        .route("/auth/sessions", get(handlers::list_sessions))
+        .route("/auth/sessions/{id}", delete(handlers::revoke_session))
+        .route("/api-keys", post(handlers::create_api_key))
+        .route("/api-keys", get(handlers::list_api_keys))
+        .route("/api-keys/{id}", delete(handlers::revoke_api_key))
+        .route("/metrics", get(metrics::export))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            metrics::track,
+        ))
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::REQUEST_TIMEOUT,
+            config.server.request_timeout,
+        ))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::make_span)
+                .on_response(telemetry::on_response),
+        )
+        .layer(middleware::from_fn(request_id::propagate))
         .with_state(state);
 
+    if let Some(cors) = cors_layer(&config.server.cors_origins) {
+        app = app.layer(cors);
+    }
+
     // Start server
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
+    let addr = std::net::SocketAddr::new(config.server.bind_address, config.server.port);
+    let listener = tokio::net::TcpListener::bind(addr)
         .await
-        .expect("Failed to bind to port 3000");
+        .unwrap_or_else(|e| panic!("Failed to bind to {addr}: {e}"));
 
-    tracing::info!("Server listening on 0.0.0.0:3000");
+    tracing::info!("Server listening on {addr}");
 
-    axum::serve(listener, app)
-        .await
-        .expect("Failed to start server");
+    // On SIGTERM/SIGINT fail readiness but keep serving for the shutdown
+    // delay, so load balancers stop sending traffic first. Then stop
+    // accepting connections and give in-flight requests up to the drain
+    // timeout to finish
+    let shutdown_delay = config.server.shutdown_delay;
+    let (draining_tx, draining_rx) = tokio::sync::oneshot::channel();
+    let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+        shutdown::signal(shutdown).await;
+        if !shutdown_delay.is_zero() {
+            tracing::info!("Still accepting connections for {shutdown_delay:?}");
+            tokio::time::sleep(shutdown_delay).await;
+        }
+        let _ = draining_tx.send(());
+    });
+    let mut server = std::pin::pin!(server.into_future());
+
+    tokio::select! {
+        result = &mut server => result.expect("Failed to start server"),
+        _ = draining_rx => {
+            tracing::info!("Draining in-flight requests");
+            let deadline = tokio::time::Instant::now() + config.server.drain_timeout;
+            match tokio::time::timeout_at(deadline, server).await {
+                Ok(result) => result.expect("Server error during shutdown"),
+                Err(_) => tracing::warn!(
+                    "In-flight requests still running after {:?}, shutting down anyway",
+                    config.server.drain_timeout
+                ),
+            }
+            // Mail queued by the last requests still goes out
+            if tokio::time::timeout_at(deadline, background.drain()).await.is_err() {
+                tracing::warn!("Background tasks still running, shutting down anyway");
+            }
+        }
+    }
+
+    if let Err(e) = db.close().await {
+        tracing::warn!("Failed to close database pool: {e}");
+    }
+    tracing::info!("Server stopped");
+    telemetry.shutdown();
 }
 
+/// CORS for the configured origins, or `None` to leave CORS disabled.
+fn cors_layer(origins: &[String]) -> Option<CorsLayer> {
+    if origins.is_empty() {
+        return None;
+    }
+
+    let allow_origin = if origins.iter().any(|o| o == "*") {
+        AllowOrigin::from(Any)
+    } else {
+        // Origins were validated when the config was loaded.
+        AllowOrigin::list(origins.iter().filter_map(|o| o.parse().ok()))
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ])
+            .allow_headers(Any)
+            .expose_headers([header::ETAG, header::LINK, request_id::REQUEST_ID_HEADER]),
+    )
+}
@@ -0,0 +1,222 @@
+//! Prometheus metrics, exposed in text format at `/metrics`.
+
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use sea_orm::DatabaseConnection;
+
+use crate::{db, AppState};
+
+/// Every metric the app exports, registered in its own [`Registry`].
+/// Cloning is cheap; clones update the same metrics.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    http_requests_in_flight: IntGauge,
+    db_query_duration: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    db_pool_max_connections: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to produce an HTTP response",
+            ),
+            &["method", "route"],
+        )?;
+        let http_requests_in_flight = IntGauge::new(
+            "http_requests_in_flight",
+            "HTTP requests currently being handled",
+        )?;
+        let db_query_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "db_query_duration_seconds",
+                "Time to execute a SQL statement",
+            )
+            .buckets(vec![
+                0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+            ]),
+            &["operation", "table", "outcome"],
+        )?;
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Open database connections by state"),
+            &["state"],
+        )?;
+        let db_pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Configured database pool size limit",
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(http_requests_in_flight.clone()))?;
+        registry.register(Box::new(db_query_duration.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(db_pool_max_connections.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            http_requests_in_flight,
+            db_query_duration,
+            db_pool_connections,
+            db_pool_max_connections,
+        })
+    }
+
+    /// Records a finished SeaORM statement; install with
+    /// [`DatabaseConnection::set_metric_callback`].
+    pub fn observe_query(&self, info: &sea_orm::metric::Info<'_>) {
+        let (operation, table) = db::describe(&info.statement.sql);
+        let outcome = if info.failed { "error" } else { "ok" };
+        self.db_query_duration
+            .with_label_values(&[operation.as_str(), table.unwrap_or("none"), outcome])
+            .observe(info.elapsed.as_secs_f64());
+    }
+
+    /// Samples the pool gauges and renders every metric.
+    fn render(&self, db: &DatabaseConnection) -> Result<String, prometheus::Error> {
+        let pool = db.get_postgres_connection_pool();
+        let idle = pool.num_idle() as i64;
+        self.db_pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        self.db_pool_connections
+            .with_label_values(&["in_use"])
+            .set(i64::from(pool.size()) - idle);
+        self.db_pool_max_connections
+            .set(i64::from(pool.options().get_max_connections()));
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+/// Middleware counting and timing requests per matched route template
+/// (`/users/{id}`, not `/users/42`), so label cardinality stays bounded.
+/// Installed with `route_layer`, so requests matching no route are not counted.
+pub async fn track(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or_else(String::new, |path| path.as_str().to_string());
+
+    let metrics = &state.metrics;
+    let in_flight = InFlight::start(&metrics.http_requests_in_flight);
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    drop(in_flight);
+    metrics
+        .http_request_duration
+        .with_label_values(&[method.as_str(), route.as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    metrics
+        .http_requests
+        .with_label_values(&[method.as_str(), route.as_str(), response.status().as_str()])
+        .inc();
+
+    response
+}
+
+/// Keeps the in-flight gauge right even when a request future is dropped
+/// early, e.g. by the timeout layer.
+struct InFlight<'a>(&'a IntGauge);
+
+impl<'a> InFlight<'a> {
+    fn start(gauge: &'a IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Serves `/metrics`. Like the health checks it needs no credentials, so
+/// deployments that are reachable publicly should block it at the proxy.
+pub async fn export(State(state): State<AppState>) -> Response {
+    match state.metrics.render(&state.db) {
+        Ok(body) => (
+            [(
+                header::CONTENT_TYPE,
+                "text/plain; version=0.0.4; charset=utf-8",
+            )],
+            body,
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to encode metrics: {e}");
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::testing::{database, state};
+
+    #[tokio::test]
+    async fn requests_are_counted_under_their_route_template() {
+        let state = state(database(vec![]).await);
+        let metrics = state.metrics.clone();
+        let app = Router::new()
+            .route("/users/{id}", get(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(state.clone(), track))
+            .with_state(state);
+
+        for uri in ["/users/1", "/users/42", "/nowhere"] {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let counted = |route: &str, status: &str| {
+            metrics
+                .http_requests
+                .with_label_values(&["GET", route, status])
+                .get()
+        };
+        assert_eq!(counted("/users/{id}", "200"), 2);
+        assert_eq!(counted("/users/42", "200"), 0);
+        assert_eq!(counted("", "404"), 0);
+        assert_eq!(
+            metrics
+                .http_request_duration
+                .with_label_values(&["GET", "/users/{id}"])
+                .get_sample_count(),
+            2
+        );
+        assert_eq!(metrics.http_requests_in_flight.get(), 0);
+    }
+}
@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Match the normalization done on save (trim, lowercase the domain),
+        // then make uniqueness case-insensitive. Index creation fails if
+        // emails differing only by case already exist; resolve those first.
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE users
+                SET email = split_part(btrim(email), '@', 1) || '@' || lower(split_part(btrim(email), '@', 2))
+                WHERE email LIKE '%@%'
+                  AND email <> split_part(btrim(email), '@', 1) || '@' || lower(split_part(btrim(email), '@', 2));
+                CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email_lower ON users (lower(email));",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared("DROP INDEX IF EXISTS idx_users_email_lower;")
+            .await?;
+        Ok(())
+    }
+}
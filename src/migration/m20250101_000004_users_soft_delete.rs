@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Only live users need unique emails, so a deleted account's address
+        // can be reused. Restoring fails with a conflict if it was taken.
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "ALTER TABLE users ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP NULL;
+                ALTER TABLE users DROP CONSTRAINT IF EXISTS users_email_key;
+                DROP INDEX IF EXISTS idx_users_email_lower;
+                CREATE UNIQUE INDEX idx_users_email_lower ON users (lower(email)) WHERE deleted_at IS NULL;",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Soft-deleted users become visible again. Fails if a deleted and a
+        // live user share an email; purge one of them first.
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "DROP INDEX IF EXISTS idx_users_email_lower;
+                CREATE UNIQUE INDEX idx_users_email_lower ON users (lower(email));
+                ALTER TABLE users ADD CONSTRAINT users_email_key UNIQUE (email);
+                ALTER TABLE users DROP COLUMN IF EXISTS deleted_at;",
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Argon2id PHC string. Nullable: users created before passwords
+        // existed can't sign in until one is set for them.
+        manager
+            .get_connection()
+            .execute_unprepared("ALTER TABLE users ADD COLUMN IF NOT EXISTS password_hash TEXT;")
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared("ALTER TABLE users DROP COLUMN IF EXISTS password_hash;")
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // The TOTP secret has to be stored as is to compute codes; recovery
+        // codes are stored as SHA-256 hashes. `last_used_step` stops a code
+        // from being replayed within its 30-second window.
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "CREATE TABLE IF NOT EXISTS totp_credentials (
+                    user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+                    secret BYTEA NOT NULL,
+                    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                    confirmed_at TIMESTAMP,
+                    last_used_step BIGINT,
+                    failed_attempts INTEGER NOT NULL DEFAULT 0,
+                    last_failed_at TIMESTAMP
+                );
+                CREATE TABLE IF NOT EXISTS recovery_codes (
+                    id BIGSERIAL PRIMARY KEY,
+                    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+                    code_hash BYTEA NOT NULL,
+                    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                    used_at TIMESTAMP
+                );
+                CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id ON recovery_codes(user_id);
+                CREATE TABLE IF NOT EXISTS mfa_required_roles (
+                    role VARCHAR(16) PRIMARY KEY
+                        CONSTRAINT mfa_required_roles_role_check CHECK (role IN ('admin', 'member')),
+                    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+                );",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "DROP TABLE IF EXISTS mfa_required_roles;
+                DROP TABLE IF EXISTS recovery_codes;
+                DROP TABLE IF EXISTS totp_credentials;",
+            )
+            .await?;
+        Ok(())
+    }
+}
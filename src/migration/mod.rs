@@ -0,0 +1,110 @@
+//! Versioned schema migrations, applied on startup and via `migrate`.
+
+use sea_orm::{ConnectionTrait, DatabaseConnection, DatabaseTransaction, DbErr, TransactionTrait};
+use sea_orm_migration::{MigrationStatus, MigrationTrait, MigratorTrait};
+
+mod m20250101_000001_create_users;
+mod m20250101_000002_users_email_case_insensitive;
+mod m20250101_000003_users_version;
+mod m20250101_000004_users_soft_delete;
+mod m20250101_000005_users_password_hash;
+mod m20250101_000006_create_refresh_tokens;
+mod m20250101_000007_users_role;
+mod m20250101_000008_create_api_keys;
+mod m20250101_000009_create_sessions;
+mod m20250101_000010_users_email_verified_at;
+mod m20250101_000011_create_password_reset_tokens;
+mod m20250101_000012_create_two_factor;
+mod m20250101_000013_users_email_verification_nonce;
+
+pub struct Migrator;
+
+impl MigratorTrait for Migrator {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        vec![
+            Box::new(m20250101_000001_create_users::Migration),
+            Box::new(m20250101_000002_users_email_case_insensitive::Migration),
+            Box::new(m20250101_000003_users_version::Migration),
+            Box::new(m20250101_000004_users_soft_delete::Migration),
+            Box::new(m20250101_000005_users_password_hash::Migration),
+            Box::new(m20250101_000006_create_refresh_tokens::Migration),
+            Box::new(m20250101_000007_users_role::Migration),
+            Box::new(m20250101_000008_create_api_keys::Migration),
+            Box::new(m20250101_000009_create_sessions::Migration),
+            Box::new(m20250101_000010_users_email_verified_at::Migration),
+            Box::new(m20250101_000011_create_password_reset_tokens::Migration),
+            Box::new(m20250101_000012_create_two_factor::Migration),
+            Box::new(m20250101_000013_users_email_verification_nonce::Migration),
+        ]
+    }
+}
+
+/// Key for the Postgres advisory lock serializing migrations across replicas.
+const MIGRATION_LOCK_KEY: i64 = 0x6178_756d_5f6d_6967;
+
+/// Starts a transaction holding the migration advisory lock.
+///
+/// The lock is transaction-scoped, so it is released on commit or rollback
+/// even if the process dies, and all migrations in a run apply atomically.
+async fn locked(db: &DatabaseConnection) -> Result<DatabaseTransaction, DbErr> {
+    let txn = db.begin().await?;
+    txn.execute_unprepared(&format!(
+        "SELECT pg_advisory_xact_lock({MIGRATION_LOCK_KEY})"
+    ))
+    .await?;
+    Ok(txn)
+}
+
+/// Applies all pending migrations. Replicas starting at the same time wait
+/// for each other, and later ones find nothing left to do.
+pub async fn run_pending(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let txn = locked(db).await?;
+    Migrator::up(&txn, None).await?;
+    txn.commit().await
+}
+
+/// Names of migrations not yet applied to `db`. Fails if `db` has applied
+/// migrations this build does not know about.
+pub async fn pending(db: &DatabaseConnection) -> Result<Vec<String>, DbErr> {
+    Ok(Migrator::get_pending_migrations(db)
+        .await?
+        .iter()
+        .map(|m| m.name().to_string())
+        .collect())
+}
+
+#[derive(clap::Subcommand)]
+pub enum MigrateAction {
+    /// Apply pending migrations (all, or the next N)
+    Up { steps: Option<u32> },
+    /// Roll back the latest N migrations (default 1)
+    Down { steps: Option<u32> },
+    /// List migrations and whether they are applied
+    Status,
+}
+
+/// Runs a `migrate` subcommand.
+pub async fn command(db: &DatabaseConnection, action: &MigrateAction) -> Result<(), DbErr> {
+    match action {
+        MigrateAction::Up { steps } => {
+            let txn = locked(db).await?;
+            Migrator::up(&txn, *steps).await?;
+            txn.commit().await
+        }
+        MigrateAction::Down { steps } => {
+            let txn = locked(db).await?;
+            Migrator::down(&txn, Some(steps.unwrap_or(1))).await?;
+            txn.commit().await
+        }
+        MigrateAction::Status => {
+            for migration in Migrator::get_migration_with_status(db).await? {
+                let status = match migration.status() {
+                    MigrationStatus::Applied => "applied",
+                    MigrationStatus::Pending => "pending",
+                };
+                println!("{status:<8} {}", migration.name());
+            }
+            Ok(())
+        }
+    }
+}
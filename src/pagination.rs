@@ -0,0 +1,150 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, NaiveDateTime};
+
+/// Which way a cursor walks through the `(created_at, id)` ordering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Next,
+    Prev,
+}
+
+/// Opaque position in the user listing, keyed on `(created_at, id)`.
+///
+/// Encoded as URL-safe base64 so clients treat it as an opaque token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub direction: Direction,
+    pub created_at: NaiveDateTime,
+    pub id: i32,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let direction = match self.direction {
+            Direction::Next => 'n',
+            Direction::Prev => 'p',
+        };
+        let micros = self.created_at.and_utc().timestamp_micros();
+        URL_SAFE_NO_PAD.encode(format!("{direction}:{micros}:{}", self.id))
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(value).ok()?).ok()?;
+        let mut parts = raw.splitn(3, ':');
+
+        let direction = match parts.next()? {
+            "n" => Direction::Next,
+            "p" => Direction::Prev,
+            _ => return None,
+        };
+        let micros = parts.next()?.parse().ok()?;
+        let id = parts.next()?.parse().ok()?;
+
+        Some(Self {
+            direction,
+            created_at: DateTime::from_timestamp_micros(micros)?.naive_utc(),
+            id,
+        })
+    }
+}
+
+/// Builds an RFC 8288 `Link` header value for the given relations.
+///
+/// `path` and `query` come from the incoming request; any existing
+/// `cursor` or `pagination` parameters are replaced by the new cursor.
+pub fn link_header(path: &str, query: Option<&str>, links: &[(&str, &str)]) -> Option<String> {
+    if links.is_empty() {
+        return None;
+    }
+
+    let retained: Vec<&str> = query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| {
+            let key = pair.split('=').next().unwrap_or_default();
+            key != "cursor" && key != "pagination"
+        })
+        .collect();
+
+    let value = links
+        .iter()
+        .map(|(rel, cursor)| {
+            let mut params = retained.clone();
+            let cursor_param = format!("cursor={cursor}");
+            params.push(&cursor_param);
+            format!("<{path}?{}>; rel=\"{rel}\"", params.join("&"))
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(direction: Direction) -> Cursor {
+        Cursor {
+            direction,
+            created_at: DateTime::from_timestamp_micros(1_735_732_800_123_456)
+                .unwrap()
+                .naive_utc(),
+            id: 42,
+        }
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        for direction in [Direction::Next, Direction::Prev] {
+            let cursor = cursor(direction);
+            assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        }
+    }
+
+    #[test]
+    fn cursors_are_url_safe() {
+        let encoded = cursor(Direction::Next).encode();
+        assert!(encoded
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'));
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        for raw in ["x:1:1", "n:1", "n:abc:1", "n:1:abc", "n:1:1:1", ""] {
+            assert_eq!(Cursor::decode(&URL_SAFE_NO_PAD.encode(raw)), None, "{raw}");
+        }
+        assert_eq!(Cursor::decode("not base64!"), None);
+    }
+
+    #[test]
+    fn link_header_is_empty_without_links() {
+        assert_eq!(link_header("/users", Some("limit=10"), &[]), None);
+    }
+
+    #[test]
+    fn link_header_replaces_the_cursor_and_keeps_other_parameters() {
+        let header = link_header(
+            "/users",
+            Some("limit=10&cursor=old&pagination=cursor&sort=name"),
+            &[("next", "abc"), ("prev", "def")],
+        );
+        assert_eq!(
+            header.as_deref(),
+            Some(
+                "</users?limit=10&sort=name&cursor=abc>; rel=\"next\", \
+                 </users?limit=10&sort=name&cursor=def>; rel=\"prev\""
+            )
+        );
+    }
+
+    #[test]
+    fn link_header_works_without_a_query() {
+        assert_eq!(
+            link_header("/users", None, &[("next", "abc")]).as_deref(),
+            Some("</users?cursor=abc>; rel=\"next\"")
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Argon2id password hashing.
+//!
+//! Hashes are PHC strings (`$argon2id$v=19$m=...,t=...,p=...$salt$hash`),
+//! so each one records the parameters it was made with. Hashes made with
+//! other parameters still verify and are flagged for rehashing.
+
+use std::sync::{Arc, OnceLock};
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier as _, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use rand::rngs::OsRng;
+
+use crate::{config::PasswordConfig, error::AppError};
+
+/// Outcome of checking a password against a stored hash.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verified {
+    Invalid,
+    /// The password matches; `rehash` is set if the hash uses outdated
+    /// parameters and should be replaced.
+    Valid {
+        rehash: bool,
+    },
+}
+
+#[derive(Clone)]
+pub struct PasswordHasher {
+    params: Params,
+    /// Hash verified against when the account doesn't exist, so a login
+    /// for an unknown email takes as long as one with a wrong password.
+    dummy_hash: Arc<OnceLock<String>>,
+}
+
+impl PasswordHasher {
+    pub fn new(config: &PasswordConfig) -> Result<Self, String> {
+        let params = Params::new(
+            config.memory_kib,
+            config.iterations,
+            config.parallelism,
+            None,
+        )
+        .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            params,
+            dummy_hash: Arc::default(),
+        })
+    }
+
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+
+    fn hash_blocking(&self, password: &str) -> Result<String, AppError> {
+        let salt = SaltString::generate(&mut OsRng);
+        self.argon2()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| AppError::Internal(format!("password hashing failed: {e}")))
+    }
+
+    fn verify_blocking(&self, hash: &str, password: &str) -> Result<Verified, AppError> {
+        let parsed = PasswordHash::new(hash)
+            .map_err(|e| AppError::Internal(format!("stored password hash is malformed: {e}")))?;
+
+        if self
+            .argon2()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_err()
+        {
+            return Ok(Verified::Invalid);
+        }
+
+        let current = parsed.algorithm.as_str() == Algorithm::Argon2id.ident().as_str()
+            && parsed.version == Some(Version::V0x13.into())
+            && Params::try_from(&parsed).is_ok_and(|p| {
+                p.m_cost() == self.params.m_cost()
+                    && p.t_cost() == self.params.t_cost()
+                    && p.p_cost() == self.params.p_cost()
+            });
+
+        Ok(Verified::Valid { rehash: !current })
+    }
+
+    /// Hashes `password` with a fresh salt, off the async runtime.
+    pub async fn hash(&self, password: String) -> Result<String, AppError> {
+        let hasher = self.clone();
+        tokio::task::spawn_blocking(move || hasher.hash_blocking(&password))
+            .await
+            .map_err(|e| AppError::Internal(format!("password hashing task failed: {e}")))?
+    }
+
+    /// Checks `password` against `hash`, or against a dummy hash when there
+    /// is no account, which always yields [`Verified::Invalid`].
+    pub async fn verify(
+        &self,
+        hash: Option<String>,
+        password: String,
+    ) -> Result<Verified, AppError> {
+        let hasher = self.clone();
+        tokio::task::spawn_blocking(move || match hash {
+            Some(hash) => hasher.verify_blocking(&hash, &password),
+            None => {
+                let dummy = match hasher.dummy_hash.get() {
+                    Some(dummy) => dummy.clone(),
+                    None => {
+                        let dummy = hasher.hash_blocking("dummy password")?;
+                        hasher.dummy_hash.get_or_init(|| dummy).clone()
+                    }
+                };
+                hasher.verify_blocking(&dummy, &password)?;
+                Ok(Verified::Invalid)
+            }
+        })
+        .await
+        .map_err(|e| AppError::Internal(format!("password verification task failed: {e}")))?
+    }
+}
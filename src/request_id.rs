@@ -0,0 +1,116 @@
+//! `X-Request-Id` handling for correlating client requests with server logs.
+//!
+//! A well-formed ID sent by the client is kept, otherwise a UUID is generated.
+//! The ID is set on the request before tracing sees it, echoed in the
+//! response, and available to error bodies via [`current`].
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied ID accepted as is.
+const MAX_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The ID of the request being handled, if called from within one.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Only short, printable IDs are trusted, so clients cannot inject
+/// arbitrary text into log lines.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LENGTH && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+pub async fn propagate(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map_or_else(|| uuid::Uuid::new_v4().to_string(), str::to_string);
+
+    // Valid IDs are printable ASCII, so this cannot fail
+    let value = HeaderValue::from_str(&id).expect("request ID is a valid header value");
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, value.clone());
+
+    let mut response = REQUEST_ID.scope(id, next.run(request)).await;
+    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[test]
+    fn short_printable_ids_are_valid() {
+        assert!(is_valid("req-42"));
+        assert!(is_valid(&"a".repeat(MAX_LENGTH)));
+    }
+
+    #[test]
+    fn empty_long_or_unprintable_ids_are_invalid() {
+        assert!(!is_valid(""));
+        assert!(!is_valid(&"a".repeat(MAX_LENGTH + 1)));
+        assert!(!is_valid("two words"));
+        assert!(!is_valid("line\nbreak"));
+        assert!(!is_valid("café"));
+    }
+
+    /// Sends a request with `id`, returning the ID the handler saw and the
+    /// one echoed in the response.
+    async fn round_trip(id: Option<&'static str>) -> (String, String) {
+        let app = Router::new()
+            .route("/", get(|| async { current().unwrap_or_default() }))
+            .layer(middleware::from_fn(propagate));
+        let mut request = Request::get("/");
+        if let Some(id) = id {
+            request = request.header(REQUEST_ID_HEADER, id);
+        }
+
+        let response = app
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let echoed = response.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (String::from_utf8(body.to_vec()).unwrap(), echoed)
+    }
+
+    #[tokio::test]
+    async fn valid_ids_are_kept() {
+        let (seen, echoed) = round_trip(Some("req-42")).await;
+        assert_eq!(seen, "req-42");
+        assert_eq!(echoed, "req-42");
+    }
+
+    #[tokio::test]
+    async fn missing_or_invalid_ids_are_replaced() {
+        for id in [None, Some("two words")] {
+            let (seen, echoed) = round_trip(id).await;
+            assert!(uuid::Uuid::parse_str(&seen).is_ok(), "{seen}");
+            assert_eq!(seen, echoed);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Log output and optional OpenTelemetry trace export.
+//!
+//! Spans always go to the log. With `telemetry.otlp_endpoint` set they are
+//! also exported over OTLP, continuing any W3C `traceparent` the caller sent.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderMap,
+    response::Response,
+};
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider as _};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing::{field::Empty, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::{
+    config::{Config, LogFormat, OtlpProtocol, TelemetryConfig},
+    db,
+    request_id::REQUEST_ID_HEADER,
+};
+
+/// Flushes exported spans on [`Telemetry::shutdown`].
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                tracing::warn!("Failed to flush trace exporter: {e}");
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber: log output plus, if configured, OTLP export.
+pub fn init(config: &Config) -> Result<Telemetry, String> {
+    let mut filter = config.log.filter.clone();
+    if let Some(directive) =
+        db::log_directive(&config.database).filter(|_| !filter.contains("sqlx"))
+    {
+        filter = format!("{filter},{directive}");
+    }
+
+    let fmt_layer = match config.log.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .boxed(),
+    };
+
+    let provider = config
+        .telemetry
+        .otlp_endpoint
+        .as_deref()
+        .map(|endpoint| tracer_provider(&config.telemetry, endpoint))
+        .transpose()?;
+    let otel_layer = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+    });
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::new(filter))
+        .with(fmt_layer)
+        .with(otel_layer)
+        .init();
+
+    Ok(Telemetry { provider })
+}
+
+fn tracer_provider(config: &TelemetryConfig, endpoint: &str) -> Result<SdkTracerProvider, String> {
+    let exporter = match config.otlp_protocol {
+        OtlpProtocol::Grpc => SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .build(),
+        // Unlike gRPC, the HTTP exporter posts to the URL as given
+        OtlpProtocol::Http => {
+            let endpoint = if endpoint.ends_with("/v1/traces") {
+                endpoint.to_string()
+            } else {
+                format!("{}/v1/traces", endpoint.trim_end_matches('/'))
+            };
+            SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()
+        }
+    }
+    .map_err(|e| format!("telemetry.otlp_endpoint: cannot create exporter: {e}"))?;
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build())
+}
+
+/// Reads propagation headers such as `traceparent` from a request.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// `TraceLayer` span for a request, named after the matched route and
+/// parented to the caller's trace when a `traceparent` header is present.
+pub fn make_span(request: &Request) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("", |path| path.as_str());
+
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        route,
+        request_id = request
+            .headers()
+            .get(&REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok()),
+        otel.name = format!("{} {route}", request.method()),
+        otel.kind = "server",
+        http.response.status_code = Empty,
+        // Filled in by the `Principal` extractor once the caller is known
+        user_id = Empty,
+    );
+
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    // Fails only when no OpenTelemetry layer is installed
+    let _ = span.set_parent(parent);
+
+    span
+}
+
+pub fn on_response(response: &Response, latency: Duration, span: &Span) {
+    // As `i64`: OpenTelemetry would export other integers as strings
+    span.record(
+        "http.response.status_code",
+        i64::from(response.status().as_u16()),
+    );
+    tracing::debug!(
+        status = response.status().as_u16(),
+        ?latency,
+        "finished processing request"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::State, routing::get, Router};
+    use opentelemetry::{trace::SpanKind, Value};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SpanData};
+    use sea_orm::{DatabaseConnection, EntityTrait};
+    use tower::ServiceExt;
+    use tower_http::trace::TraceLayer;
+
+    use super::*;
+    use crate::{db::Traced, entities::user, testing::database};
+
+    fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<&'a Value> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| &kv.value)
+    }
+
+    async fn find_user(State(db): State<Traced<DatabaseConnection>>) -> &'static str {
+        user::Entity::find_by_id(7).one(&db).await.unwrap();
+        "ok"
+    }
+
+    #[tokio::test]
+    async fn queries_are_exported_under_the_request_span() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route("/users/{id}", get(find_user))
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(make_span)
+                    .on_response(on_response),
+            )
+            .with_state(Traced(database(vec![vec![]]).await));
+        let request = Request::get("/users/7").body(Body::empty()).unwrap();
+        app.oneshot(request).await.unwrap();
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let request = spans.iter().find(|s| s.name == "GET /users/{id}").unwrap();
+        let query = spans.iter().find(|s| s.name == "SELECT users").unwrap();
+
+        assert_eq!(request.span_kind, SpanKind::Server);
+        assert_eq!(
+            attribute(request, "route"),
+            Some(&Value::from("/users/{id}"))
+        );
+        assert_eq!(
+            attribute(request, "http.response.status_code"),
+            Some(&Value::I64(200))
+        );
+        assert_eq!(query.span_kind, SpanKind::Client);
+        assert_eq!(query.parent_span_id, request.span_context.span_id());
+        assert_eq!(
+            query.span_context.trace_id(),
+            request.span_context.trace_id()
+        );
+        assert_eq!(
+            attribute(query, "db.system"),
+            Some(&Value::from("postgresql"))
+        );
+        assert_eq!(
+            attribute(query, "db.operation"),
+            Some(&Value::from("SELECT"))
+        );
+        assert_eq!(
+            attribute(query, "db.sql.table"),
+            Some(&Value::from("users"))
+        );
+    }
+}
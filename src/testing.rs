@@ -0,0 +1,146 @@
+//! A scripted database and app state for tests of code that talks to
+//! Postgres.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use sea_orm::{
+    Database, DatabaseBackend, DatabaseConnection, DbErr, EntityTrait, IdenStatic, Iterable,
+    ModelTrait, ProxyDatabaseTrait, ProxyExecResult, ProxyRow, Statement, Value,
+};
+
+use crate::{
+    auth::{jwt::Jwt, password_reset, verify_email},
+    background::Background,
+    config::Config,
+    db::Traced,
+    mail::MemoryMailer,
+    metrics::Metrics,
+    password::PasswordHasher,
+    shutdown::ShutdownFlag,
+    throttle::Throttle,
+    AppState,
+};
+
+/// Answers queries with canned rows, in order; statements that aren't
+/// queries succeed without doing anything. Every statement is recorded.
+#[derive(Clone, Debug, Default)]
+pub struct Script {
+    results: Arc<Mutex<VecDeque<Vec<ProxyRow>>>>,
+    statements: Arc<Mutex<Vec<String>>>,
+}
+
+impl Script {
+    pub fn new(results: Vec<Vec<ProxyRow>>) -> Self {
+        Self {
+            results: Arc::new(Mutex::new(results.into())),
+            statements: Arc::default(),
+        }
+    }
+
+    pub async fn connect(&self) -> DatabaseConnection {
+        let proxy: Box<dyn ProxyDatabaseTrait> = Box::new(self.clone());
+        Database::connect_proxy(DatabaseBackend::Postgres, Arc::new(proxy))
+            .await
+            .unwrap()
+    }
+
+    /// The SQL run so far, with values inlined.
+    pub fn statements(&self) -> Vec<String> {
+        self.statements.lock().unwrap().clone()
+    }
+
+    /// Whether a statement ran that contains every one of `fragments`.
+    pub fn ran(&self, fragments: &[&str]) -> bool {
+        self.statements()
+            .iter()
+            .any(|sql| fragments.iter().all(|fragment| sql.contains(fragment)))
+    }
+}
+
+#[async_trait::async_trait]
+impl ProxyDatabaseTrait for Script {
+    async fn query(&self, statement: Statement) -> Result<Vec<ProxyRow>, DbErr> {
+        self.statements.lock().unwrap().push(statement.to_string());
+        self.results
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| DbErr::Custom(format!("unexpected query: {}", statement.sql)))
+    }
+
+    async fn execute(&self, statement: Statement) -> Result<ProxyExecResult, DbErr> {
+        self.statements.lock().unwrap().push(statement.to_string());
+        Ok(ProxyExecResult::new(0, 1))
+    }
+}
+
+/// Connects to a database that answers with `results`, in order.
+pub async fn database(results: Vec<Vec<ProxyRow>>) -> DatabaseConnection {
+    Script::new(results).connect().await
+}
+
+pub fn row(values: impl IntoIterator<Item = (&'static str, Value)>) -> ProxyRow {
+    ProxyRow::new(
+        values
+            .into_iter()
+            .map(|(column, value)| (column.to_string(), value))
+            .collect(),
+    )
+}
+
+/// A row for an `into_tuple` query. Those read columns by position, and a
+/// proxy row orders its columns by name, so the names here are positions.
+pub fn tuple(values: impl IntoIterator<Item = Value>) -> ProxyRow {
+    ProxyRow::new(
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(i, value)| (format!("{i:02}"), value))
+            .collect(),
+    )
+}
+
+/// The row a query for `model` would return.
+pub fn model_row<M: ModelTrait>(model: &M) -> ProxyRow {
+    ProxyRow::new(
+        <M::Entity as EntityTrait>::Column::iter()
+            .map(|column| (column.as_str().to_string(), model.get(column)))
+            .collect(),
+    )
+}
+
+/// The row `SELECT COUNT(*)` returns.
+pub fn count(n: i64) -> ProxyRow {
+    row([("num_items", Value::from(n))])
+}
+
+/// App state over `db`, with the JWT secret and admin token tests sign
+/// requests with, and mail kept in memory.
+pub fn state(db: DatabaseConnection) -> AppState {
+    let mut config = Config::default();
+    config.jwt.secret = Some("0123456789abcdef0123456789abcdef".to_string());
+    config.auth.admin_token = Some("admin-token".to_string());
+    AppState {
+        db: Traced(db),
+        admin_token: config.auth.admin_token.clone(),
+        shutdown: ShutdownFlag::default(),
+        health_check_timeout: config.database.health_check_timeout,
+        metrics: Metrics::new().unwrap(),
+        passwords: PasswordHasher::new(&config.password).unwrap(),
+        jwt: Jwt::new(&config.jwt).unwrap(),
+        session: config.session.clone(),
+        trusted_origins: Vec::new(),
+        mailer: Arc::new(MemoryMailer::default()),
+        email_verification_ttl: config.users.email_verification_ttl,
+        verify_email_url: config.mail.verify_email_url.clone(),
+        password_reset_ttl: config.users.password_reset_ttl,
+        reset_password_url: config.mail.reset_password_url.clone(),
+        password_reset_throttle: Throttle::new(password_reset::RESEND_INTERVAL),
+        verification_throttle: Throttle::new(verify_email::RESEND_INTERVAL),
+        totp: config.totp.clone(),
+        background: Background::new(1),
+    }
+}
@@ -0,0 +1,72 @@
+//! In-memory limits on how often something may happen per key, e.g. per
+//! email address.
+//!
+//! Each instance keeps its own record, so this only sheds repeats before
+//! they reach the database; checks made there stay authoritative.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Keys remembered at once. Past this, keys still inside their interval
+/// aren't recorded, and are left to the database checks.
+const MAX_KEYS: usize = 10_000;
+
+#[derive(Clone)]
+pub struct Throttle {
+    interval: Duration,
+    last: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl Throttle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: Arc::default(),
+        }
+    }
+
+    /// Whether `key` may go ahead: it may once per interval.
+    pub fn allow(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(at) = last.get(key) {
+            if now.duration_since(*at) < self.interval {
+                return false;
+            }
+        }
+
+        if last.len() >= MAX_KEYS {
+            last.retain(|_, at| now.duration_since(*at) < self.interval);
+        }
+        if last.len() < MAX_KEYS || last.contains_key(key) {
+            last.insert(key.to_string(), now);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_within_the_interval_are_refused() {
+        let throttle = Throttle::new(Duration::from_secs(60));
+
+        assert!(throttle.allow("jane@example.com"));
+        assert!(!throttle.allow("jane@example.com"));
+        assert!(throttle.allow("john@example.com"));
+    }
+
+    #[test]
+    fn keys_are_allowed_again_after_the_interval() {
+        let throttle = Throttle::new(Duration::ZERO);
+
+        assert!(throttle.allow("jane@example.com"));
+        assert!(throttle.allow("jane@example.com"));
+    }
+}
@@ -0,0 +1,38 @@
+//! Shared helpers for validating request DTOs with the `validator` crate.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer};
+use validator::ValidationError;
+
+/// Maximum length of `VARCHAR(255)` columns such as `users.name`.
+pub const MAX_TEXT_LENGTH: u64 = 255;
+
+/// Password length bounds. The upper bound keeps hashing cost predictable.
+pub const MIN_PASSWORD_LENGTH: u64 = 8;
+pub const MAX_PASSWORD_LENGTH: u64 = 128;
+
+/// Deserializes a string with leading and trailing whitespace removed.
+pub fn trimmed<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    Ok(value.trim().to_string())
+}
+
+/// Rejects strings containing control characters (newlines, NUL, escapes...).
+pub fn no_control_chars(value: &str) -> Result<(), ValidationError> {
+    if value.chars().any(char::is_control) {
+        return Err(ValidationError::new("control_characters")
+            .with_message("must not contain control characters".into()));
+    }
+    Ok(())
+}
+
+/// Rejects timestamps that have already passed.
+pub fn in_future(value: &DateTime<Utc>) -> Result<(), ValidationError> {
+    if *value <= Utc::now() {
+        return Err(ValidationError::new("in_past").with_message("must be in the future".into()));
+    }
+    Ok(())
+}